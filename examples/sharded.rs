@@ -0,0 +1,56 @@
+use std::time::Instant;
+use transaction_engine::{ClientId, Currency, Operation, ShardedEngine};
+
+const NUMBER_OF_CLIENTS: u32 = 65_536;
+const NUMBER_OF_SHARDS: usize = 10;
+const NUMBER_OF_TRANSACTIONS: u32 = 65_536_000;
+
+fn main() {
+    // Initialize the sharded transaction engine.
+    let mut engine = ShardedEngine::init(NUMBER_OF_SHARDS);
+    let currency = Currency::default();
+
+    // Start the timer.
+    let now = Instant::now();
+
+    // Route 65.536 million transactions to the shards by going through each client account in a
+    // round-robin manner 1000 times. Make deposits for the first half and withdrawals for the
+    // second half.
+    for tx in 0..NUMBER_OF_TRANSACTIONS {
+        let client = (tx % NUMBER_OF_CLIENTS) as ClientId;
+        let amount = 1;
+        if tx < NUMBER_OF_TRANSACTIONS / 2 {
+            engine.submit(Operation::Deposit {
+                client,
+                tx,
+                amount,
+                currency,
+            });
+        } else {
+            engine.submit(Operation::Withdrawal {
+                client,
+                tx,
+                amount,
+                currency,
+            });
+        }
+    }
+
+    // Wait for all shards to process their transactions.
+    let accounts = engine.collect_accounts();
+
+    // Stop the timer.
+    let elapsed = now.elapsed();
+
+    // Validate that each client account is empty and unlocked.
+    assert_eq!(NUMBER_OF_CLIENTS as usize, accounts.len());
+    for (_, account) in accounts {
+        assert_eq!(0, account.get_available_balance());
+        assert_eq!(0, account.get_held_balance());
+        assert_eq!(0, account.get_total_balance());
+        assert!(!account.is_locked());
+    }
+
+    // Print the result of the benchmark.
+    dbg!(elapsed);
+}
@@ -1,13 +1,20 @@
 use std::time::Instant;
-use transaction_engine::{ClientId, TransactionEngine};
+use transaction_engine::{ClientId, Storage, TransactionEngine, VecStorage};
 
 const NUMBER_OF_CLIENTS: u32 = 65_536;
 const NUMBER_OF_TRANSACTIONS: u32 = 65_536_000;
 
 fn main() {
-    // Initialize the transaction engine.
-    let mut engine = TransactionEngine::init();
+    // Initialize the transaction engine, with the `VecStorage` backend if `--vec` is passed.
+    if std::env::args().any(|arg| arg == "--vec") {
+        let storage = VecStorage::with_capacity(NUMBER_OF_TRANSACTIONS as usize);
+        run(TransactionEngine::with_storage(storage).unwrap());
+    } else {
+        run(TransactionEngine::init());
+    }
+}
 
+fn run<S: Storage>(mut engine: TransactionEngine<S>) {
     // Start the timer.
     let now = Instant::now();
 
@@ -1,17 +1,17 @@
 use std::time::Duration;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::watch::Receiver;
-use transaction_engine::{Amount, ClientId, TransactionEngine, TransactionId};
+use transaction_engine::{EngineActor, Operation, TransactionEngine};
 
 const TCP_SERVER_ADDRESS: &str = "127.0.0.1:8080";
 const TRANSACTION_BUFFER_SIZE: usize = 10_000;
 
 #[tokio::main]
 async fn main() {
-    let mut engine = TransactionEngine::init();
-    let (sender, mut receiver) = tokio::sync::mpsc::channel(TRANSACTION_BUFFER_SIZE);
+    // For max performance, pin the engine thread to an isolated core.
+    let actor = EngineActor::spawn(TransactionEngine::init(), TRANSACTION_BUFFER_SIZE);
 
     // watch channel: the ctrl-c task will notify all other tasks to shutdown
     let (watch_tx, watch_rx) = tokio::sync::watch::channel(false);
@@ -25,43 +25,22 @@ async fn main() {
         watch_tx.send(true).unwrap();
     });
 
-    // For max performance, pin this thread to an isolated core.
-    let engine_shutdown = shutdown.clone();
-    std::thread::spawn(move || {
-        // For max performance, use `try_recv` in a spin loop instead of `blocking_recv`.
-        while let Some(transaction) = receiver.blocking_recv() {
-            use Transaction::*;
-            let result = match transaction {
-                Deposit { client, tx, amount } => engine.deposit(client, tx, amount),
-                Withdrawal { client, tx, amount } => engine.withdrawal(client, tx, amount),
-                Dispute { client, tx } => engine.dispute(client, tx),
-                Resolve { client, tx } => engine.resolve(client, tx),
-                Chargeback { client, tx } => engine.chargeback(client, tx),
-            };
-            if let Err(e) = result {
-                eprintln!("transaction error: {}", e);
-            }
-        }
-        println!("beginned shutting down transaction engine");
-        std::thread::sleep(Duration::from_secs(1));
-        println!("finished shutting down transaction engine");
-        drop(engine_shutdown);
-    });
-
     let listener = TcpListener::bind(TCP_SERVER_ADDRESS).await.unwrap();
     loop {
         tokio::select! {
             result = listener.accept() => {
                 let (stream, peer) = result.unwrap();
-                let sender = sender.clone();
+                let handle = actor.handle();
                 let mut stream_shutdown = shutdown.clone();
                 tokio::spawn(async move {
                     let mut stream = TransactionStream::from(stream);
                     loop {
                         tokio::select! {
-                            maybe_transaction = stream.next() => {
-                                if let Some(transaction) = maybe_transaction {
-                                    sender.send(transaction).await.unwrap();
+                            maybe_operation = stream.next() => {
+                                if let Some(operation) = maybe_operation {
+                                    if let Err(e) = handle.submit(operation).await {
+                                        eprintln!("transaction error: {}", e);
+                                    }
                                 } else {
                                     println!("connection closed by peer: {}", peer);
                                     break;
@@ -86,11 +65,16 @@ async fn main() {
         };
     }
 
-    drop(sender);
     drop(shutdown);
 
-    println!("waiting for connections and transaction engine to shutdown gracefully");
+    println!("waiting for connections to shutdown gracefully");
     let _ = mpsx_rx.recv().await;
+
+    // Every connection is closed, so this processes the last queued transactions.
+    println!("beginned shutting down transaction engine");
+    let engine = actor.shutdown().await;
+    println!("finished shutting down transaction engine");
+    println!("{} client accounts", engine.iter_accounts().count());
     println!("exiting");
 }
 
@@ -99,6 +83,8 @@ struct Shutdown {
     // Receiver half of watch channel to start shutting down current task.
     receiver: Receiver<bool>,
     // Sending half of mpsc channel to notify main task that shutdown is completed.
+    // It's never read: the main task is notified when it's dropped.
+    #[allow(dead_code)]
     sender: Sender<()>,
 }
 
@@ -109,58 +95,26 @@ impl Shutdown {
 }
 
 struct TransactionStream {
-    buffer: [u8; 4096],
-    stream: TcpStream,
+    lines: Lines<BufReader<TcpStream>>,
 }
 
 impl TransactionStream {
     fn from(stream: TcpStream) -> Self {
         Self {
-            buffer: [0; 4096],
-            stream,
+            lines: BufReader::new(stream).lines(),
         }
     }
 
-    async fn next(&mut self) -> Option<Transaction> {
-        // Implement the logic to extract a transaction from the socket.
-        // Errors can be handled internally, or could be returned to caller.
-        let n = self.stream.read(&mut self.buffer).await.unwrap();
-        // Do stuff...
-        if n == 0 {
-            None
-        } else {
-            Some(Transaction::Deposit {
-                client: 1,
-                tx: 1,
-                amount: 1,
-            })
+    async fn next(&mut self) -> Option<Operation> {
+        // Each line is an operation serialized as JSON, e.g.:
+        // {"type":"deposit","client":1,"tx":1,"amount":10000}
+        // Invalid lines are skipped, and any I/O error closes the connection.
+        loop {
+            let line = self.lines.next_line().await.ok()??;
+            match serde_json::from_str(&line) {
+                Ok(operation) => return Some(operation),
+                Err(e) => eprintln!("invalid operation {:?}: {}", line, e),
+            }
         }
     }
 }
-
-#[allow(dead_code)]
-#[derive(Debug)]
-enum Transaction {
-    Deposit {
-        client: ClientId,
-        tx: TransactionId,
-        amount: Amount,
-    },
-    Withdrawal {
-        client: ClientId,
-        tx: TransactionId,
-        amount: Amount,
-    },
-    Dispute {
-        client: ClientId,
-        tx: TransactionId,
-    },
-    Resolve {
-        client: ClientId,
-        tx: TransactionId,
-    },
-    Chargeback {
-        client: ClientId,
-        tx: TransactionId,
-    },
-}
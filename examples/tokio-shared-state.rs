@@ -1,14 +1,13 @@
-use parking_lot::Mutex;
 use std::sync::Arc;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
 use tokio::net::{TcpListener, TcpStream};
-use transaction_engine::{Amount, ClientId, TransactionEngine, TransactionId};
+use transaction_engine::{ConcurrentEngine, Operation};
 
 const TCP_SERVER_ADDRESS: &str = "127.0.0.1:8080";
 
 #[tokio::main]
 async fn main() {
-    let engine = Arc::new(Mutex::new(TransactionEngine::init()));
+    let engine = Arc::new(ConcurrentEngine::init());
 
     let listener = TcpListener::bind(TCP_SERVER_ADDRESS).await.unwrap();
     loop {
@@ -16,18 +15,8 @@ async fn main() {
         let engine = engine.clone();
         tokio::spawn(async move {
             let mut stream = TransactionStream::from(stream);
-            while let Some(transaction) = stream.next().await {
-                use Transaction::*;
-                let result = match transaction {
-                    Deposit { client, tx, amount } => engine.lock().deposit(client, tx, amount),
-                    Withdrawal { client, tx, amount } => {
-                        engine.lock().withdrawal(client, tx, amount)
-                    }
-                    Dispute { client, tx } => engine.lock().dispute(client, tx),
-                    Resolve { client, tx } => engine.lock().resolve(client, tx),
-                    Chargeback { client, tx } => engine.lock().chargeback(client, tx),
-                };
-                if let Err(e) = result {
+            while let Some(operation) = stream.next().await {
+                if let Err(e) = engine.process(operation) {
                     eprintln!("transaction error: {}", e);
                 }
             }
@@ -36,58 +25,26 @@ async fn main() {
 }
 
 struct TransactionStream {
-    buffer: [u8; 4096],
-    stream: TcpStream,
+    lines: Lines<BufReader<TcpStream>>,
 }
 
 impl TransactionStream {
     fn from(stream: TcpStream) -> Self {
         Self {
-            buffer: [0; 4096],
-            stream,
+            lines: BufReader::new(stream).lines(),
         }
     }
 
-    async fn next(&mut self) -> Option<Transaction> {
-        // Implement the logic to extract a transaction from the socket.
-        // Errors can be handled internally, or could be returned to caller.
-        let n = self.stream.read(&mut self.buffer).await.unwrap();
-        // Do stuff...
-        if n == 0 {
-            None
-        } else {
-            Some(Transaction::Deposit {
-                client: 1,
-                tx: 1,
-                amount: 1,
-            })
+    async fn next(&mut self) -> Option<Operation> {
+        // Each line is an operation serialized as JSON, e.g.:
+        // {"type":"deposit","client":1,"tx":1,"amount":10000}
+        // Invalid lines are skipped, and any I/O error closes the connection.
+        loop {
+            let line = self.lines.next_line().await.ok()??;
+            match serde_json::from_str(&line) {
+                Ok(operation) => return Some(operation),
+                Err(e) => eprintln!("invalid operation {:?}: {}", line, e),
+            }
         }
     }
 }
-
-#[allow(dead_code)]
-#[derive(Debug)]
-enum Transaction {
-    Deposit {
-        client: ClientId,
-        tx: TransactionId,
-        amount: Amount,
-    },
-    Withdrawal {
-        client: ClientId,
-        tx: TransactionId,
-        amount: Amount,
-    },
-    Dispute {
-        client: ClientId,
-        tx: TransactionId,
-    },
-    Resolve {
-        client: ClientId,
-        tx: TransactionId,
-    },
-    Chargeback {
-        client: ClientId,
-        tx: TransactionId,
-    },
-}
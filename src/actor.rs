@@ -0,0 +1,132 @@
+use crate::{HashMapStorage, Operation, Result, Storage, SubmitError, TransactionEngine};
+use tokio::sync::{mpsc, oneshot};
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// An async front-end that owns a transaction engine on a dedicated thread.
+///
+/// Operations are submitted through a bounded queue: when it's full, submitting an operation
+/// waits until the engine catches up, which applies backpressure to the callers. Each operation
+/// is processed in the order it was queued, and its result is returned to the caller that
+/// submitted it. Any number of tasks can submit operations concurrently through cloned
+/// `EngineHandle`s.
+///
+/// This type is only available with the `actor` cargo feature.
+///
+/// ## Example
+/// ```
+/// use transaction_engine::{Currency, EngineActor, Operation, TransactionEngine};
+/// use transaction_engine::{SubmitError, TransactionError};
+///
+/// # tokio_test();
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn tokio_test() {
+/// let actor = EngineActor::spawn(TransactionEngine::init(), 100);
+/// let handle = actor.handle();
+///
+/// let currency = Currency::default();
+/// let deposit = Operation::Deposit { client: 1, tx: 1, amount: 100, currency };
+/// handle.submit(deposit).await.unwrap();
+/// let withdrawal = Operation::Withdrawal { client: 1, tx: 2, amount: 150, currency };
+/// let error = SubmitError::Transaction(TransactionError::InsufficientAvailableFunds);
+/// assert_eq!(Err(error), handle.submit(withdrawal).await);
+///
+/// // Shutting down the actor returns the engine, once every queued operation was processed.
+/// let engine = actor.shutdown().await;
+/// assert_eq!(100, engine.get_account(1).unwrap().get_available_balance());
+/// assert_eq!(Err(SubmitError::ShutDown), handle.submit(deposit).await);
+/// # }
+/// ```
+pub struct EngineActor<S: Storage + 'static = HashMapStorage> {
+    handle: EngineHandle,
+    stopped: oneshot::Receiver<TransactionEngine<S>>,
+}
+
+impl<S: Storage + 'static> EngineActor<S> {
+    /// Moves a transaction engine to a new dedicated thread, with a queue of at most `capacity`
+    /// operations (at least 1) waiting to be processed.
+    pub fn spawn(engine: TransactionEngine<S>, capacity: usize) -> Self {
+        assert!(capacity > 0, "an engine actor needs a non-empty queue");
+        let (sender, receiver) = mpsc::channel(capacity);
+        let (stop, stopped) = oneshot::channel();
+        std::thread::spawn(move || run(engine, receiver, stop));
+        Self {
+            handle: EngineHandle { sender },
+            stopped,
+        }
+    }
+
+    /// Returns a handle to submit operations to the engine, which can be cloned and moved
+    /// to other tasks.
+    pub fn handle(&self) -> EngineHandle {
+        self.handle.clone()
+    }
+
+    /// Shuts down the actor gracefully and returns its engine.
+    ///
+    /// Every operation submitted before this call (even if it's still waiting for room in the
+    /// queue) is processed first, and its result returned. Operations submitted afterwards
+    /// through any handle are rejected with `SubmitError::ShutDown`.
+    pub async fn shutdown(self) -> TransactionEngine<S> {
+        // The engine thread only exits after receiving this message, or once every handle
+        // is dropped, which can't happen while `self` holds one.
+        let _ = self.handle.sender.send(Message::Shutdown).await;
+        // The engine thread returns its engine, unless it panicked.
+        self.stopped.await.expect("engine thread panicked")
+    }
+}
+
+/// A handle to submit operations to an `EngineActor`.
+#[derive(Clone)]
+pub struct EngineHandle {
+    sender: mpsc::Sender<Message>,
+}
+
+impl EngineHandle {
+    /// Submits a single operation to the engine, and waits until it's processed.
+    ///
+    /// If the queue is full, this first waits until there's room for the operation.
+    pub async fn submit(&self, operation: Operation) -> std::result::Result<(), SubmitError> {
+        let (reply, result) = oneshot::channel();
+        let message = Message::Operation(operation, reply);
+        let sent = self.sender.send(message).await;
+        sent.map_err(|_| SubmitError::ShutDown)?;
+        // An operation is dropped without a reply only if the engine thread panicked.
+        let result = result.await.map_err(|_| SubmitError::ShutDown)?;
+        Ok(result?)
+    }
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+enum Message {
+    /// Processes an operation and replies with its result.
+    Operation(Operation, oneshot::Sender<Result<()>>),
+    /// Stops the engine thread, which returns the engine.
+    Shutdown,
+}
+
+/// Processes the messages of an engine actor until it's shut down or every handle is dropped.
+fn run<S: Storage>(
+    mut engine: TransactionEngine<S>,
+    mut receiver: mpsc::Receiver<Message>,
+    stop: oneshot::Sender<TransactionEngine<S>>,
+) {
+    while let Some(message) = receiver.blocking_recv() {
+        match message {
+            Message::Operation(operation, reply) => {
+                // The caller may have stopped waiting for the result.
+                let _ = reply.send(engine.process(operation));
+            }
+            Message::Shutdown => break,
+        }
+    }
+    // Dropping the receiver rejects the operations queued after the shutdown message, since
+    // their reply channels are dropped along with them.
+    drop(receiver);
+    let _ = stop.send(engine);
+}
@@ -1,4 +1,8 @@
-use crate::{Balance, Result, Transaction, TransactionError, TransactionKind, TransactionState};
+use crate::WithdrawalDisputePolicy;
+use crate::{Amount, Balance, Currency, NegativeBalanceDisputePolicy, Result, Transaction};
+use crate::{SpecialTransactionKind, TransactionError, TransactionKind, TransactionState};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /******************************************
  *               PUBLIC API               *
@@ -7,20 +11,120 @@ use crate::{Balance, Result, Transaction, TransactionError, TransactionKind, Tra
 /// A unique 16-bit ID for a client account.
 pub type ClientId = u16;
 
-/// A client account.
+/// A client account, which holds one wallet per currency.
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Client {
+    wallets: BTreeMap<Currency, Wallet>,
+    /// The credit limits that aren't the default, which are kept apart from the wallets so
+    /// that setting a limit doesn't create a wallet.
+    limits: BTreeMap<Currency, CreditLimit>,
+    status: AccountStatus,
+}
+
+/// The balances of a client account in a single currency.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Wallet {
     available: Balance,
     total: Balance,
-    locked: bool,
+    shortfall: Balance,
+}
+
+/// The credit limit of a client account in a single currency, which allows withdrawals
+/// (as well as transfers and authorizations) past a zero balance.
+///
+/// By default, there's no overdraft and no floor (i.e. the available balance can't go below
+/// zero because of a withdrawal, but it can still go negative because of a dispute).
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct CreditLimit {
+    /// How far below zero a withdrawal can take the available balance.
+    pub overdraft: Amount,
+    /// The lowest total balance a withdrawal can leave, if any.
+    pub floor: Option<Balance>,
+}
+
+/// The status of a client account.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    /// Active when the client account is created or when it's unlocked or unfrozen.
+    Active,
+    /// Locked when a transaction is reversed by a chargeback, until it's unlocked by an admin.
+    Locked,
+    /// Frozen when an active account is frozen by an admin, until it's unfrozen by an admin.
+    Frozen,
+    /// Closed when an empty account is closed by an admin.
+    /// FINAL: the status can't change at this point.
+    Closed,
 }
 
 impl Client {
-    /// Returns the available balance in this client account.
+    /// Returns the available balance in the default currency in this client account.
+    pub fn get_available_balance(&self) -> Balance {
+        self.get_default_wallet().get_available_balance()
+    }
+
+    /// Returns the held balance in the default currency in this client account.
+    pub fn get_held_balance(&self) -> Balance {
+        self.get_default_wallet().get_held_balance()
+    }
+
+    /// Returns the total balance in the default currency in this client account.
+    pub fn get_total_balance(&self) -> Balance {
+        self.get_default_wallet().get_total_balance()
+    }
+
+    /// Returns the disputed amount in the default currency in this client account that
+    /// couldn't be held (see `NegativeBalanceDisputePolicy`).
+    pub fn get_shortfall(&self) -> Balance {
+        self.get_default_wallet().get_shortfall()
+    }
+
+    /// Returns the wallet of the given currency in this client account,
+    /// or `None` if no transaction in that currency was ever accepted.
+    pub fn get_wallet(&self, currency: Currency) -> Option<&Wallet> {
+        self.wallets.get(&currency)
+    }
+
+    /// Returns the credit limit of this client account in the given currency.
+    pub fn get_credit_limit(&self, currency: Currency) -> CreditLimit {
+        self.limits.get(&currency).copied().unwrap_or_default()
+    }
+
+    /// Returns an iterator over all wallets in this client account, sorted by currency.
+    pub fn iter_wallets(&self) -> impl Iterator<Item = (Currency, &Wallet)> {
+        self.wallets
+            .iter()
+            .map(|(&currency, wallet)| (currency, wallet))
+    }
+
+    /// Returns the status of this client account.
+    pub fn get_status(&self) -> AccountStatus {
+        self.status
+    }
+
+    /// Returns whether this client account is locked.
+    pub fn is_locked(&self) -> bool {
+        self.status == AccountStatus::Locked
+    }
+
+    /// Returns whether this client account is frozen.
+    pub fn is_frozen(&self) -> bool {
+        self.status == AccountStatus::Frozen
+    }
+
+    /// Returns whether this client account is closed.
+    pub fn is_closed(&self) -> bool {
+        self.status == AccountStatus::Closed
+    }
+}
+
+impl Wallet {
+    /// Returns the available balance in this wallet.
     pub fn get_available_balance(&self) -> Balance {
         self.available
     }
 
-    /// Returns the held balance in this client account.
+    /// Returns the held balance in this wallet.
     pub fn get_held_balance(&self) -> Balance {
         // The transaction engine *must* guarantee that this method does NOT overflow.
         // The implementation *should* guarantee that this unwrap won't panic.
@@ -28,14 +132,15 @@ impl Client {
         self.total.checked_sub(self.available).unwrap()
     }
 
-    /// Returns the total balance in this client account.
+    /// Returns the total balance in this wallet.
     pub fn get_total_balance(&self) -> Balance {
         self.total
     }
 
-    /// Returns whether this client account is locked.
-    pub fn is_locked(&self) -> bool {
-        self.locked
+    /// Returns the disputed amount in this wallet that couldn't be held because it exceeded
+    /// the available balance (see `NegativeBalanceDisputePolicy`).
+    pub fn get_shortfall(&self) -> Balance {
+        self.shortfall
     }
 }
 
@@ -44,17 +149,325 @@ impl Client {
  *******************************************/
 
 impl Client {
-    /// Constructs a new client account with a balance of zero.
+    /// Constructs a new client account without any wallet (i.e. with a balance of zero).
     pub(crate) fn init() -> Self {
         Self {
-            available: 0,
-            total: 0,
-            locked: false,
+            wallets: BTreeMap::new(),
+            limits: BTreeMap::new(),
+            status: AccountStatus::Active,
+        }
+    }
+
+    /// Returns the wallet of the default currency, or an empty wallet if it doesn't exist.
+    fn get_default_wallet(&self) -> Wallet {
+        self.get_wallet(Currency::default())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns an `Error` if this client account isn't active.
+    pub(crate) fn check_active(&self) -> Result<()> {
+        match self.status {
+            AccountStatus::Active => Ok(()),
+            AccountStatus::Locked => Err(TransactionError::ClientAccountLocked),
+            AccountStatus::Frozen => Err(TransactionError::ClientAccountFrozen),
+            AccountStatus::Closed => Err(TransactionError::ClientAccountClosed),
+        }
+    }
+
+    /// Attempts to unlock this client account.
+    pub(crate) fn try_unlock(&mut self) -> Result<()> {
+        match self.status {
+            AccountStatus::Locked => self.status = AccountStatus::Active,
+            AccountStatus::Closed => return Err(TransactionError::ClientAccountClosed),
+            _ => return Err(TransactionError::ClientAccountNotLocked),
+        }
+        Ok(())
+    }
+
+    /// Attempts to freeze this client account.
+    pub(crate) fn try_freeze(&mut self) -> Result<()> {
+        match self.status {
+            AccountStatus::Frozen => return Err(TransactionError::ClientAccountAlreadyFrozen),
+            _ => self.check_active()?,
+        }
+        self.status = AccountStatus::Frozen;
+        Ok(())
+    }
+
+    /// Attempts to unfreeze this client account.
+    pub(crate) fn try_unfreeze(&mut self) -> Result<()> {
+        match self.status {
+            AccountStatus::Frozen => self.status = AccountStatus::Active,
+            AccountStatus::Closed => return Err(TransactionError::ClientAccountClosed),
+            _ => return Err(TransactionError::ClientAccountNotFrozen),
+        }
+        Ok(())
+    }
+
+    /// Attempts to set the credit limit of the given currency in this client account.
+    pub(crate) fn try_set_credit_limit(
+        &mut self,
+        currency: Currency,
+        limit: CreditLimit,
+    ) -> Result<()> {
+        if self.status == AccountStatus::Closed {
+            return Err(TransactionError::ClientAccountClosed);
+        }
+        // The overdraft *must* fit in a `Balance` so that withdrawals can't overflow.
+        if limit.overdraft > Balance::MAX as Amount {
+            return Err(TransactionError::TransactionAmountTooLarge);
         }
+        if limit == CreditLimit::default() {
+            self.limits.remove(&currency);
+        } else {
+            self.limits.insert(currency, limit);
+        }
+        Ok(())
     }
 
-    /// Attempts to process a deposit in this client account.
-    pub(crate) fn try_deposit(&mut self, amount: Balance) -> Result<()> {
+    /// Attempts to close this client account.
+    pub(crate) fn try_close(&mut self) -> Result<()> {
+        if self.status == AccountStatus::Closed {
+            return Err(TransactionError::ClientAccountClosed);
+        }
+        if self
+            .wallets
+            .values()
+            .any(|w| w.available != 0 || w.total != 0 || w.shortfall != 0)
+        {
+            return Err(TransactionError::ClientAccountNotEmpty);
+        }
+        self.status = AccountStatus::Closed;
+        Ok(())
+    }
+
+    /// Attempts to process a deposit in the given currency in this client account.
+    pub(crate) fn try_deposit(&mut self, currency: Currency, amount: Balance) -> Result<()> {
+        self.try_update_wallet(currency, |wallet| wallet.try_deposit(amount))
+    }
+
+    /// Attempts to process a withdrawal in the given currency in this client account.
+    pub(crate) fn try_withdrawal(&mut self, currency: Currency, amount: Balance) -> Result<()> {
+        let limit = self.get_credit_limit(currency);
+        self.try_update_wallet(currency, |wallet| wallet.try_withdrawal(amount, limit))
+    }
+
+    /// Attempts to process a dispute of `amount` (i.e. part or all of the undisputed amount
+    /// of `old_transaction`) in this client account.
+    ///
+    /// The `policy` determines how a withdrawal (or a captured authorization) is disputed,
+    /// and the `balance_policy` how a deposit that exceeds the available balance is disputed.
+    /// A withdrawal that is already partly disputed keeps holding a pending credit (or not)
+    /// like when it was first disputed, so that its whole disputed amount is resolved or
+    /// charged back the same way.
+    pub(crate) fn try_dispute(
+        &mut self,
+        old_transaction: &mut Transaction,
+        amount: Balance,
+        policy: WithdrawalDisputePolicy,
+        balance_policy: NegativeBalanceDisputePolicy,
+    ) -> Result<()> {
+        let mut shortfall = 0;
+        let credit = match old_transaction.kind {
+            TransactionKind::Deposit | TransactionKind::Transfer => false,
+            _ if old_transaction.held > 0 => old_transaction.credit,
+            _ => policy == WithdrawalDisputePolicy::HoldCredit,
+        };
+        self.try_update_wallet(old_transaction.currency, |wallet| {
+            match (old_transaction.kind, policy) {
+                (TransactionKind::Deposit, _) => {
+                    shortfall = wallet.try_hold(amount, balance_policy)?
+                }
+                (TransactionKind::Transfer, _) => {}
+                (_, WithdrawalDisputePolicy::Reject) => {
+                    return Err(TransactionError::WithdrawalNotDisputable)
+                }
+                _ if credit => wallet.total = checked_add_balance(wallet.total, amount)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        old_transaction.held += amount;
+        old_transaction.shortfall += shortfall;
+        old_transaction.credit = credit;
+        old_transaction.state = TransactionState::Disputed;
+        Ok(())
+    }
+
+    /// Attempts to process a resolve of `amount` (i.e. part or all of the disputed amount
+    /// of `old_transaction`) in this client account.
+    ///
+    /// A withdrawal is resolved according to the policy it was disputed with. For a deposit,
+    /// the shortfall of the dispute (if any) is settled first, since it wasn't held.
+    pub(crate) fn try_resolve(
+        &mut self,
+        old_transaction: &mut Transaction,
+        amount: Balance,
+    ) -> Result<()> {
+        let settled = amount.min(old_transaction.shortfall);
+        self.try_update_wallet(old_transaction.currency, |wallet| {
+            match (old_transaction.kind, old_transaction.credit) {
+                (TransactionKind::Deposit, _) => wallet.try_release(amount, settled)?,
+                (_, false) => {}
+                (_, true) => wallet.total = checked_sub_balance(wallet.total, amount)?,
+            }
+            Ok(())
+        })?;
+        old_transaction.held -= amount;
+        old_transaction.shortfall -= settled;
+        old_transaction.update_state();
+        Ok(())
+    }
+
+    /// Attempts to process a chargeback of `amount` (i.e. part or all of the disputed amount
+    /// of `old_transaction`) in this client account.
+    ///
+    /// A withdrawal is charged back according to the policy it was disputed with. For a deposit,
+    /// the shortfall of the dispute (if any) is settled first: since it wasn't held, it's
+    /// debited from the available balance.
+    pub(crate) fn try_chargeback(
+        &mut self,
+        old_transaction: &mut Transaction,
+        amount: Balance,
+    ) -> Result<()> {
+        let settled = amount.min(old_transaction.shortfall);
+        self.try_update_wallet(old_transaction.currency, |wallet| {
+            match (old_transaction.kind, old_transaction.credit) {
+                (TransactionKind::Deposit, _) => wallet.try_reverse(amount, settled)?,
+                (_, false) => wallet.try_deposit(amount)?,
+                (_, true) => wallet.available = checked_add_balance(wallet.available, amount)?,
+            }
+            Ok(())
+        })?;
+        old_transaction.held -= amount;
+        old_transaction.shortfall -= settled;
+        old_transaction.reversed += amount;
+        old_transaction.update_state();
+        self.status = AccountStatus::Locked;
+        Ok(())
+    }
+
+    /// Attempts to process an authorization in the given currency in this client account.
+    pub(crate) fn try_authorize(&mut self, currency: Currency, amount: Balance) -> Result<()> {
+        let limit = self.get_credit_limit(currency);
+        self.try_update_wallet(currency, |wallet| wallet.try_authorize(amount, limit))
+    }
+
+    /// Attempts to process a capture of `amount` (i.e. part or all of the authorized amount
+    /// of `old_transaction`) in this client account. The rest of the amount is released.
+    pub(crate) fn try_capture(
+        &mut self,
+        old_transaction: &mut Transaction,
+        amount: Balance,
+    ) -> Result<()> {
+        let released = old_transaction.amount - amount;
+        self.try_update_wallet(old_transaction.currency, |wallet| {
+            wallet.total = checked_sub_balance(wallet.total, amount)?;
+            wallet.available = checked_add_balance(wallet.available, released)?;
+            Ok(())
+        })?;
+        old_transaction.amount = amount;
+        old_transaction.state = TransactionState::Accepted;
+        Ok(())
+    }
+
+    /// Attempts to process a void of `old_transaction` in this client account.
+    pub(crate) fn try_void(&mut self, old_transaction: &mut Transaction) -> Result<()> {
+        let amount = old_transaction.amount;
+        self.try_update_wallet(old_transaction.currency, |wallet| {
+            wallet.available = checked_add_balance(wallet.available, amount)?;
+            Ok(())
+        })?;
+        old_transaction.state = TransactionState::Voided;
+        Ok(())
+    }
+
+    /// Attempts to process the recipient's side of a dispute, resolve, or chargeback of `amount`
+    /// of a transfer received by this client account.
+    ///
+    /// The funds are held, released, or reversed just like for a deposit (including the
+    /// `balance_policy` of a dispute), regardless of the status of this client account (unless
+    /// it's closed), since it didn't initiate the dispute. The shortfall of a dispute is added to
+    /// `old_transaction`, and it's settled by the sender's side of a resolve or chargeback.
+    pub(crate) fn try_update_received(
+        &mut self,
+        kind: &SpecialTransactionKind,
+        old_transaction: &mut Transaction,
+        amount: Balance,
+        balance_policy: NegativeBalanceDisputePolicy,
+    ) -> Result<()> {
+        if self.status == AccountStatus::Closed {
+            return Err(TransactionError::ClientAccountClosed);
+        }
+        let settled = amount.min(old_transaction.shortfall);
+        let mut shortfall = 0;
+        self.try_update_wallet(old_transaction.currency, |wallet| {
+            match kind {
+                SpecialTransactionKind::Dispute => {
+                    shortfall = wallet.try_hold(amount, balance_policy)?
+                }
+                SpecialTransactionKind::Resolve => wallet.try_release(amount, settled)?,
+                SpecialTransactionKind::Chargeback => wallet.try_reverse(amount, settled)?,
+            }
+            Ok(())
+        })?;
+        old_transaction.shortfall += shortfall;
+        Ok(())
+    }
+
+    /// Attempts to charge a fee in the given currency to this client account.
+    ///
+    /// If `limited`, the fee must fit within the credit limit, just like a withdrawal.
+    /// Otherwise, it's charged even if the available balance becomes negative.
+    pub(crate) fn try_charge_fee(
+        &mut self,
+        currency: Currency,
+        fee: Balance,
+        limited: bool,
+    ) -> Result<()> {
+        let limit = self.get_credit_limit(currency);
+        self.try_update_wallet(currency, |wallet| {
+            if limited {
+                return wallet.try_withdrawal(fee, limit);
+            }
+            wallet.available = checked_sub_balance(wallet.available, fee)?;
+            wallet.total = checked_sub_balance(wallet.total, fee)?;
+            Ok(())
+        })
+    }
+
+    /// Attempts to collect a fee in the given currency into this client account
+    /// (i.e. the fee account, or a client account that is refunded a fee).
+    ///
+    /// The fee is collected regardless of the status of this client account (unless it's
+    /// closed), since it didn't initiate the transaction.
+    pub(crate) fn try_collect_fee(&mut self, currency: Currency, fee: Balance) -> Result<()> {
+        if self.status == AccountStatus::Closed {
+            return Err(TransactionError::ClientAccountClosed);
+        }
+        self.try_update_wallet(currency, |wallet| wallet.try_deposit(fee))
+    }
+
+    /// Attempts to update the wallet of the given currency in this client account,
+    /// which is created if it doesn't already exist.
+    ///
+    /// The update is performed on a copy of the wallet, so that it's left untouched
+    /// (or not created at all) if the update returns an `Error`.
+    fn try_update_wallet<F>(&mut self, currency: Currency, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut Wallet) -> Result<()>,
+    {
+        let mut wallet = self.wallets.get(&currency).copied().unwrap_or_default();
+        update(&mut wallet)?;
+        self.wallets.insert(currency, wallet);
+        Ok(())
+    }
+}
+
+impl Wallet {
+    /// Attempts to process a deposit in this wallet.
+    fn try_deposit(&mut self, amount: Balance) -> Result<()> {
         self.total = checked_add_balance(self.total, amount)?;
         // The transaction engine *must* guarantee that this method does NOT overflow.
         // The implementation *should* guarantee that this unwrap won't panic.
@@ -63,46 +476,86 @@ impl Client {
         Ok(())
     }
 
-    /// Attempts to process a withdrawal in this client account.
-    pub(crate) fn try_withdrawal(&mut self, amount: Balance) -> Result<()> {
-        if amount > self.available {
-            return Err(TransactionError::InsufficientAvailableFunds);
+    /// Returns an `Error` if `amount` can't be debited from this wallet given its credit limit.
+    fn check_credit_limit(&self, amount: Balance, limit: CreditLimit) -> Result<()> {
+        // These computations are performed with `i128` so that they can't overflow.
+        let overdraft = i128::from(limit.overdraft);
+        if i128::from(amount) > i128::from(self.available) + overdraft {
+            return Err(match overdraft {
+                0 => TransactionError::InsufficientAvailableFunds,
+                _ => TransactionError::OverdraftLimitExceeded,
+            });
+        }
+        if let Some(floor) = limit.floor {
+            if i128::from(self.total) - i128::from(amount) < i128::from(floor) {
+                return Err(TransactionError::TotalBalanceBelowFloor);
+            }
         }
-        // The transaction engine *must* guarantee that this method does NOT overflow.
-        // The implementation *should* guarantee that these unwraps won't panic.
-        // However, if there's a bug, better death than dishonor.
-        self.available = checked_sub_balance(self.available, amount).unwrap();
-        self.total = checked_sub_balance(self.total, amount).unwrap();
         Ok(())
     }
 
-    /// Attempts to process a dispute in this client account.
-    pub(crate) fn try_dispute(&mut self, old_transaction: &mut Transaction) -> Result<()> {
-        if let TransactionKind::Deposit = old_transaction.kind {
-            self.available = checked_sub_balance(self.available, old_transaction.amount)?;
-        }
-        old_transaction.state = TransactionState::Disputed;
+    /// Attempts to hold `amount` of the available funds of this wallet for a dispute, given the
+    /// `balance_policy`. Returns the shortfall (i.e. the part of `amount` that wasn't held).
+    fn try_hold(
+        &mut self,
+        amount: Balance,
+        balance_policy: NegativeBalanceDisputePolicy,
+    ) -> Result<Balance> {
+        let held = match balance_policy {
+            NegativeBalanceDisputePolicy::Reject if amount > self.available => {
+                return Err(TransactionError::DisputeExceedsAvailableFunds)
+            }
+            NegativeBalanceDisputePolicy::CapHold => amount.min(self.available.max(0)),
+            _ => amount,
+        };
+        let shortfall = amount - held;
+        self.available = checked_sub_balance(self.available, held)?;
+        self.shortfall = checked_add_balance(self.shortfall, shortfall)?;
+        Ok(shortfall)
+    }
+
+    /// Attempts to release `amount` of the disputed funds of this wallet, of which `settled`
+    /// is the shortfall of the dispute that wasn't held.
+    fn try_release(&mut self, amount: Balance, settled: Balance) -> Result<()> {
+        self.available = checked_add_balance(self.available, amount - settled)?;
+        self.shortfall = checked_sub_balance(self.shortfall, settled)?;
         Ok(())
     }
 
-    /// Attempts to process a resolve in this client account.
-    pub(crate) fn try_resolve(&mut self, old_transaction: &mut Transaction) -> Result<()> {
-        if let TransactionKind::Deposit = old_transaction.kind {
-            self.available = checked_add_balance(self.available, old_transaction.amount)?;
-        }
-        old_transaction.state = TransactionState::Accepted;
+    /// Attempts to reverse `amount` of the disputed funds of this wallet, of which `settled`
+    /// is the shortfall of the dispute that wasn't held (so it's debited from the available funds).
+    fn try_reverse(&mut self, amount: Balance, settled: Balance) -> Result<()> {
+        self.total = checked_sub_balance(self.total, amount)?;
+        self.available = checked_sub_balance(self.available, settled)?;
+        self.shortfall = checked_sub_balance(self.shortfall, settled)?;
         Ok(())
     }
 
-    /// Attempts to process a chargeback in this client account.
-    pub(crate) fn try_chargeback(&mut self, old_transaction: &mut Transaction) -> Result<()> {
-        let amount = old_transaction.amount;
-        match old_transaction.kind {
-            TransactionKind::Deposit => self.total = checked_sub_balance(self.total, amount)?,
-            TransactionKind::Withdrawal => self.try_deposit(amount)?,
+    /// Attempts to process an authorization in this wallet (i.e. hold available funds).
+    fn try_authorize(&mut self, amount: Balance, limit: CreditLimit) -> Result<()> {
+        self.check_credit_limit(amount, limit)?;
+        // The held balance (i.e. the total balance minus the available balance) *must* fit in
+        // a `Balance`, which an overdraft no longer guarantees. This is computed with `i128`
+        // so that it can't overflow.
+        let held = i128::from(self.total) - (i128::from(self.available) - i128::from(amount));
+        if held > i128::from(Balance::MAX) {
+            return Err(TransactionError::BalanceWouldOverflow);
         }
-        old_transaction.state = TransactionState::Reversed;
-        self.locked = true;
+        // The transaction engine *must* guarantee that this method does NOT overflow.
+        // The implementation *should* guarantee that this unwrap won't panic.
+        // However, if there's a bug, better death than dishonor.
+        self.available = checked_sub_balance(self.available, amount).unwrap();
+        Ok(())
+    }
+
+    /// Attempts to process a withdrawal in this wallet.
+    fn try_withdrawal(&mut self, amount: Balance, limit: CreditLimit) -> Result<()> {
+        self.check_credit_limit(amount, limit)?;
+        // The transaction engine *must* guarantee that this method does NOT overflow.
+        // The implementation *should* guarantee that these unwraps won't panic.
+        // However, if there's a bug, better death than dishonor.
+        self.available = checked_sub_balance(self.available, amount).unwrap();
+        self.total = checked_sub_balance(self.total, amount).unwrap();
         Ok(())
     }
 }
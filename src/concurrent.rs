@@ -0,0 +1,341 @@
+use crate::{Amount, Client, ClientId, CrossClientDisputePolicy, Currency, Event, Operation};
+use crate::{NegativeBalanceDisputePolicy, Result, Storage, Transaction, TransactionEngine};
+use crate::{TransactionId, TransactionIter, TransactionView, WithdrawalDisputePolicy};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// A transaction engine that can be shared between threads (e.g. in an `Arc`) and processes
+/// operations through a shared reference, with fine-grained locking.
+///
+/// Every client account has its own lock, and transactions are spread across a fixed number of
+/// locks by ID. An operation only locks the transaction it creates or references, and then the
+/// client accounts it may modify (in increasing order of ID, so that operations can't deadlock).
+/// Operations on different client accounts and transactions are therefore processed in parallel.
+///
+/// Each operation is processed by the exact same logic as `TransactionEngine`, so a rejected
+/// operation leaves every client account and transaction unmodified. However, this engine
+/// doesn't support fees, dispute windows, journals, or snapshots.
+///
+/// ## Example
+/// ```
+/// use std::sync::Arc;
+/// use transaction_engine::ConcurrentEngine;
+///
+/// let engine = Arc::new(ConcurrentEngine::init());
+/// let threads = (0..4)
+///     .map(|client| {
+///         let engine = engine.clone();
+///         std::thread::spawn(move || {
+///             for i in 0..100 {
+///                 engine.deposit(client, u32::from(client) * 100 + i, 1_0000).unwrap();
+///             }
+///         })
+///     })
+///     .collect::<Vec<_>>();
+/// threads.into_iter().for_each(|thread| thread.join().unwrap());
+///
+/// assert_eq!(4, engine.collect_accounts().len());
+/// assert_eq!(100_0000, engine.get_account(3).unwrap().get_total_balance());
+/// ```
+pub struct ConcurrentEngine {
+    /// One slot per client ID, indexed by ID.
+    clients: Box<[Mutex<Option<Client>>]>,
+    /// The transactions, spread across stripes by ID.
+    stripes: Box<[Mutex<HashMap<TransactionId, Transaction>>]>,
+    observers: Vec<Observer>,
+    cross_client_disputes: CrossClientDisputePolicy,
+    withdrawal_disputes: WithdrawalDisputePolicy,
+    negative_balance_disputes: NegativeBalanceDisputePolicy,
+}
+
+impl ConcurrentEngine {
+    /// Constructs a new concurrent engine with no history of client accounts or transactions.
+    pub fn init() -> Self {
+        let clients = (0..=ClientId::MAX).map(|_| Mutex::new(None)).collect();
+        let stripes = (0..STRIPES).map(|_| Mutex::new(HashMap::new())).collect();
+        Self {
+            clients,
+            stripes,
+            observers: Vec::new(),
+            cross_client_disputes: CrossClientDisputePolicy::Reject,
+            withdrawal_disputes: WithdrawalDisputePolicy::NoHold,
+            negative_balance_disputes: NegativeBalanceDisputePolicy::Allow,
+        }
+    }
+
+    /// See `TransactionEngine::set_cross_client_dispute_policy`.
+    pub fn set_cross_client_dispute_policy(&mut self, policy: CrossClientDisputePolicy) {
+        self.cross_client_disputes = policy;
+    }
+
+    /// See `TransactionEngine::set_withdrawal_dispute_policy`.
+    pub fn set_withdrawal_dispute_policy(&mut self, policy: WithdrawalDisputePolicy) {
+        self.withdrawal_disputes = policy;
+    }
+
+    /// See `TransactionEngine::set_negative_balance_dispute_policy`.
+    pub fn set_negative_balance_dispute_policy(&mut self, policy: NegativeBalanceDisputePolicy) {
+        self.negative_balance_disputes = policy;
+    }
+
+    /// Registers an observer that is called with an `Event` after every processed transaction,
+    /// whether it was accepted or rejected (see `TransactionEngine::subscribe`).
+    ///
+    /// The observer is called from the thread that processed the transaction, once its locks
+    /// are released, so events of different threads may be observed in any order.
+    pub fn subscribe<F: Fn(&Event) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Returns a copy of a single client account by ID.
+    pub fn get_account(&self, client: ClientId) -> Option<Client> {
+        lock(&self.clients[usize::from(client)]).clone()
+    }
+
+    /// Returns a copy of all client accounts sorted by ID.
+    ///
+    /// The client accounts are locked one at a time, so they may reflect different points
+    /// in time if operations are processed concurrently.
+    pub fn collect_accounts(&self) -> Vec<(ClientId, Client)> {
+        let iter = ClientId::MIN..=ClientId::MAX;
+        iter.filter_map(|id| Some((id, self.get_account(id)?)))
+            .collect()
+    }
+
+    /// Returns a single transaction by ID.
+    pub fn get_transaction(&self, tx: TransactionId) -> Option<TransactionView> {
+        let transaction = *lock(self.get_stripe(tx)).get(&tx)?;
+        Some(TransactionView::new(tx, transaction))
+    }
+
+    /// Attempts to process a single operation.
+    pub fn process(&self, operation: Operation) -> Result<()> {
+        self.process_with_event(operation, false).0
+    }
+
+    /// Attempts to process a single operation, and returns its outcome as an `Event`
+    /// (see `TransactionEngine::apply`).
+    pub fn apply(&self, operation: Operation) -> Event {
+        let (_, event) = self.process_with_event(operation, true);
+        // The event is always built when it's requested.
+        event.expect("event wasn't built")
+    }
+
+    /// See `TransactionEngine::deposit`.
+    pub fn deposit(&self, client: ClientId, tx: TransactionId, amount: Amount) -> Result<()> {
+        let currency = Currency::default();
+        self.process(Operation::Deposit {
+            client,
+            tx,
+            amount,
+            currency,
+        })
+    }
+
+    /// See `TransactionEngine::withdrawal`.
+    pub fn withdrawal(&self, client: ClientId, tx: TransactionId, amount: Amount) -> Result<()> {
+        let currency = Currency::default();
+        self.process(Operation::Withdrawal {
+            client,
+            tx,
+            amount,
+            currency,
+        })
+    }
+
+    /// See `TransactionEngine::transfer`.
+    pub fn transfer(
+        &self,
+        from: ClientId,
+        to: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+    ) -> Result<()> {
+        let currency = Currency::default();
+        self.process(Operation::Transfer {
+            client: from,
+            tx,
+            to,
+            amount,
+            currency,
+        })
+    }
+
+    /// See `TransactionEngine::dispute`.
+    pub fn dispute(&self, client: ClientId, tx: TransactionId) -> Result<()> {
+        let amount = None;
+        self.process(Operation::Dispute { client, tx, amount })
+    }
+
+    /// See `TransactionEngine::resolve`.
+    pub fn resolve(&self, client: ClientId, tx: TransactionId) -> Result<()> {
+        let amount = None;
+        self.process(Operation::Resolve { client, tx, amount })
+    }
+
+    /// See `TransactionEngine::chargeback`.
+    pub fn chargeback(&self, client: ClientId, tx: TransactionId) -> Result<()> {
+        let amount = None;
+        self.process(Operation::Chargeback { client, tx, amount })
+    }
+}
+
+impl Default for ConcurrentEngine {
+    fn default() -> Self {
+        Self::init()
+    }
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+/// The number of stripes of transactions.
+const STRIPES: usize = 256;
+
+type Observer = Box<dyn Fn(&Event) + Send + Sync>;
+
+impl ConcurrentEngine {
+    /// Attempts to process a single operation, and builds its event if `event` is true or if
+    /// there's at least one observer to notify.
+    fn process_with_event(&self, operation: Operation, event: bool) -> (Result<()>, Option<Event>) {
+        // Lock the transaction created or referenced by the operation (if any).
+        let mut stripe = operation.tx().map(|tx| lock(self.get_stripe(tx)));
+
+        // Lock the client accounts the operation may modify, in increasing order of ID.
+        let mut ids = vec![operation.client()];
+        if let Operation::Transfer { to, .. } = operation {
+            ids.push(to);
+        }
+        if let (Some(stripe), Some(tx)) = (&stripe, operation.tx()) {
+            if let Some(transaction) = stripe.get(&tx) {
+                ids.push(transaction.client);
+                ids.extend(transaction.recipient);
+            }
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        let mut guards = ids
+            .iter()
+            .map(|&id| lock(&self.clients[usize::from(id)]))
+            .collect::<Vec<_>>();
+
+        // Process the operation with a transaction engine that only sees the locked state.
+        let storage = LockedStorage {
+            clients: ids
+                .iter()
+                .copied()
+                .zip(guards.iter_mut().map(|guard| &mut **guard))
+                .collect(),
+            transactions: stripe.as_deref_mut(),
+        };
+        let mut engine = TransactionEngine::from_storage(storage);
+        engine.set_cross_client_dispute_policy(self.cross_client_disputes);
+        engine.set_withdrawal_dispute_policy(self.withdrawal_disputes);
+        engine.set_negative_balance_dispute_policy(self.negative_balance_disputes);
+        let event = event || !self.observers.is_empty();
+        let (result, event) = engine.execute_with_event(operation, event);
+
+        // Release the locks before notifying the observers.
+        drop(engine);
+        drop(guards);
+        drop(stripe);
+        if let Some(event) = &event {
+            self.observers.iter().for_each(|observer| observer(event));
+        }
+        (result, event)
+    }
+
+    fn get_stripe(&self, tx: TransactionId) -> &Mutex<HashMap<TransactionId, Transaction>> {
+        &self.stripes[tx as usize % STRIPES]
+    }
+}
+
+/// The state locked by a single operation of a `ConcurrentEngine`.
+struct LockedStorage<'a> {
+    clients: Vec<(ClientId, &'a mut Option<Client>)>,
+    transactions: Option<&'a mut HashMap<TransactionId, Transaction>>,
+}
+
+impl LockedStorage<'_> {
+    /// Returns the slot of a locked client account.
+    fn slot(&mut self, id: ClientId) -> &mut Option<Client> {
+        // The concurrent engine locks every client account an operation may modify.
+        let slot = self.clients.iter_mut().find(|(locked, _)| *locked == id);
+        slot.map(|(_, slot)| &mut **slot)
+            .expect("client account isn't locked")
+    }
+}
+
+impl Storage for LockedStorage<'_> {
+    fn get_client(&self, id: ClientId) -> Option<&Client> {
+        let slot = self.clients.iter().find(|(locked, _)| *locked == id)?;
+        slot.1.as_ref()
+    }
+
+    fn get_client_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+        let slot = self.clients.iter_mut().find(|(locked, _)| *locked == id)?;
+        slot.1.as_mut()
+    }
+
+    fn insert_client(&mut self, id: ClientId, client: Client) {
+        *self.slot(id) = Some(client);
+    }
+
+    fn remove_client(&mut self, id: ClientId) {
+        *self.slot(id) = None;
+    }
+
+    fn iter_clients(&self) -> Box<dyn Iterator<Item = (ClientId, &Client)> + '_> {
+        let iter = self.clients.iter();
+        Box::new(iter.filter_map(|(id, slot)| Some((*id, slot.as_ref()?))))
+    }
+
+    fn get_transaction(&self, tx: TransactionId) -> std::io::Result<Option<Transaction>> {
+        let transactions = self.transactions.as_ref();
+        Ok(transactions.and_then(|transactions| transactions.get(&tx).copied()))
+    }
+
+    fn insert_transaction(
+        &mut self,
+        tx: TransactionId,
+        transaction: Transaction,
+    ) -> std::io::Result<()> {
+        // The concurrent engine locks the transaction of an operation.
+        let transactions = self
+            .transactions
+            .as_mut()
+            .expect("transaction isn't locked");
+        transactions.insert(tx, transaction);
+        Ok(())
+    }
+
+    fn remove_transaction(&mut self, tx: TransactionId) -> std::io::Result<()> {
+        if let Some(transactions) = &mut self.transactions {
+            transactions.remove(&tx);
+        }
+        Ok(())
+    }
+
+    fn iter_transactions(&self) -> TransactionIter<'_> {
+        let iter = self
+            .transactions
+            .iter()
+            .flat_map(|transactions| transactions.iter());
+        Box::new(iter.map(|(&tx, &transaction)| Ok((tx, transaction))))
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        unreachable!("the locked state of a concurrent engine is never cleared")
+    }
+}
+
+/// Locks a mutex. If another thread panicked while holding it, the state it protects may be
+/// inconsistent, so this thread panics too.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().expect("lock poisoned by a panicked thread")
+}
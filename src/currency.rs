@@ -0,0 +1,90 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// The code of a currency (or any other asset), such as `USD` or `BTC`.
+///
+/// A code is made of up to 8 ASCII alphanumeric characters. The empty code is the default
+/// currency, which is used by all the methods of the transaction engine that don't specify one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; CURRENCY_LENGTH]);
+
+/// An error returned when parsing an invalid currency code.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+#[error("invalid currency code (expected up to 8 ascii alphanumeric characters)")]
+pub struct ParseCurrencyError;
+
+impl Currency {
+    /// Returns whether this is the default currency.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the code of this currency (i.e. the empty string for the default currency).
+    pub fn as_str(&self) -> &str {
+        let length = self
+            .0
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(CURRENCY_LENGTH);
+        // The code was validated as ASCII when this currency was parsed.
+        std::str::from_utf8(&self.0[..length]).expect("currency code isn't ASCII")
+    }
+}
+
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        if code.len() > CURRENCY_LENGTH || !code.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(ParseCurrencyError);
+        }
+        let mut bytes = [0; CURRENCY_LENGTH];
+        bytes[..code.len()].copy_from_slice(code.as_bytes());
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        let string = String::deserialize(deserializer)?;
+        string.parse().map_err(Error::custom)
+    }
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+/// The maximum length of a currency code.
+const CURRENCY_LENGTH: usize = 8;
+
+impl Currency {
+    /// Returns the code of this currency as a fixed-size array, padded with zeros.
+    pub(crate) fn to_bytes(self) -> [u8; CURRENCY_LENGTH] {
+        self.0
+    }
+
+    /// Constructs a currency from a code returned by `to_bytes`.
+    pub(crate) fn from_bytes(bytes: [u8; CURRENCY_LENGTH]) -> Self {
+        Self(bytes)
+    }
+}
@@ -0,0 +1,338 @@
+use crate::{Client, ClientId, Storage, Transaction, TransactionId, TransactionIter};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// A storage that keeps the client accounts and the most recent transactions in memory,
+/// and spills older transactions to a file on disk.
+///
+/// Up to `capacity` transactions are kept in memory. Beyond that, the oldest ones are written
+/// to the file, except for pending transactions (i.e. disputed or authorized), which always
+/// stay in memory. A transaction that was spilled is still cached: disputing it is just slower,
+/// since it's read back from the file (and kept in memory again once it's modified).
+///
+/// The file holds one fixed-size record per transaction ID, at an offset proportional to the ID.
+/// It's a sparse file on most file systems, so it only takes as much disk space as the number of
+/// spilled transactions (rounded up to whole blocks), even though its length is proportional to
+/// the largest spilled ID (up to ~206 GB for `TransactionId::MAX`).
+///
+/// The file is only a cache: it's truncated when the storage is opened, and the state of the
+/// transaction engine is persisted by its journal and snapshots like with any other storage.
+/// If the file can't be read or written, the operation that accessed it is rejected with
+/// `StorageFailure` and the storage is left unmodified.
+pub struct DiskStorage {
+    clients: HashMap<ClientId, Client>,
+    /// The transactions kept in memory.
+    hot: HashMap<TransactionId, Transaction>,
+    /// The IDs of the transactions kept in memory, from oldest to newest. It may also contain
+    /// the IDs of transactions that were removed since, which are skipped.
+    order: VecDeque<TransactionId>,
+    capacity: usize,
+    file: File,
+    /// The number of records in the file (i.e. the largest spilled ID + 1).
+    records: u64,
+}
+
+impl DiskStorage {
+    /// Opens a new empty storage that keeps up to `capacity` transactions in memory,
+    /// and spills the others to the file at the given path. The file is created if it doesn't
+    /// already exist, or truncated otherwise.
+    ///
+    /// Returns an `Error` if the file can't be opened.
+    ///
+    /// ## Example
+    /// ```
+    /// use transaction_engine::{DiskStorage, TransactionEngine};
+    ///
+    /// let path = std::env::temp_dir().join("transaction_engine_disk_storage_example");
+    /// let storage = DiskStorage::open(&path, 1).unwrap();
+    /// let mut engine = TransactionEngine::with_storage(storage).unwrap();
+    ///
+    /// // The 1st deposit is spilled to disk, but it can still be disputed.
+    /// engine.deposit(1, 1, 1_0000).unwrap();
+    /// engine.deposit(1, 2, 2_0000).unwrap();
+    /// engine.dispute(1, 1).unwrap();
+    ///
+    /// assert_eq!(1_0000, engine.get_account(1).unwrap().get_held_balance());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P, capacity: usize) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self {
+            clients: HashMap::new(),
+            hot: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+            file,
+            records: 0,
+        })
+    }
+}
+
+impl Storage for DiskStorage {
+    fn get_client(&self, id: ClientId) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    fn get_client_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+        self.clients.get_mut(&id)
+    }
+
+    fn insert_client(&mut self, id: ClientId, client: Client) {
+        self.clients.insert(id, client);
+    }
+
+    fn remove_client(&mut self, id: ClientId) {
+        self.clients.remove(&id);
+    }
+
+    fn iter_clients(&self) -> Box<dyn Iterator<Item = (ClientId, &Client)> + '_> {
+        Box::new(self.clients.iter().map(|(&id, client)| (id, client)))
+    }
+
+    fn get_transaction(&self, tx: TransactionId) -> Result<Option<Transaction>> {
+        match self.hot.get(&tx) {
+            Some(&transaction) => Ok(Some(transaction)),
+            None => self.read_record(tx),
+        }
+    }
+
+    fn insert_transaction(&mut self, tx: TransactionId, transaction: Transaction) -> Result<()> {
+        if let Some(hot) = self.hot.get_mut(&tx) {
+            *hot = transaction;
+            return Ok(());
+        }
+
+        // Make room for the transaction before inserting it, so that it isn't inserted if the
+        // file can't be written. Without any room, it's spilled right away (unless pending).
+        if self.capacity == 0 && !transaction.is_pending() {
+            return self.write_record(tx, Some(transaction));
+        }
+        self.spill(self.capacity.saturating_sub(1))?;
+
+        // A transaction is either in memory or on disk, never both. If it was spilled,
+        // its record is cleared so that it's only kept in memory again.
+        if self.read_record(tx)?.is_some() {
+            self.write_record(tx, None)?;
+        }
+        self.hot.insert(tx, transaction);
+        self.order.push_back(tx);
+        Ok(())
+    }
+
+    fn remove_transaction(&mut self, tx: TransactionId) -> Result<()> {
+        if self.hot.remove(&tx).is_some() {
+            // Transactions are usually removed from oldest to newest once they expire.
+            // Otherwise, drop the IDs of removed transactions once they pile up.
+            if self.order.front() == Some(&tx) {
+                self.order.pop_front();
+            } else if self.order.len() > 2 * self.hot.len().max(self.capacity) {
+                let hot = &self.hot;
+                self.order.retain(|tx| hot.contains_key(tx));
+            }
+        } else if u64::from(tx) < self.records {
+            self.write_record(tx, None)?;
+        }
+        Ok(())
+    }
+
+    fn iter_transactions(&self) -> TransactionIter<'_> {
+        let hot = self
+            .hot
+            .iter()
+            .map(|(&tx, &transaction)| Ok((tx, transaction)));
+        // The spilled transactions are read sequentially, a chunk of records at a time.
+        let cold = (0..self.records)
+            .step_by(CHUNK_RECORDS)
+            .flat_map(move |first| self.read_chunk(first));
+        Box::new(hot.chain(cold))
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        // The file is truncated first, or stale transactions would be read back.
+        self.file.set_len(0)?;
+        self.records = 0;
+        self.clients.clear();
+        self.hot.clear();
+        self.order.clear();
+        Ok(())
+    }
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+/// The length of a transaction record in the file.
+const RECORD_LENGTH: usize = 1 + Transaction::ENCODED_LENGTH;
+
+/// The number of records read at once when iterating over the file.
+const CHUNK_RECORDS: usize = 1024;
+
+impl DiskStorage {
+    /// Writes the oldest transactions kept in memory to the file until at most `limit`
+    /// of them are left, skipping pending transactions. If there are too many pending
+    /// transactions, more than `limit` transactions are kept in memory.
+    ///
+    /// Returns an `Error` if the file can't be written. In that case, the transaction that
+    /// couldn't be spilled is kept in memory.
+    fn spill(&mut self, limit: usize) -> Result<()> {
+        let mut remaining = self.order.len();
+        while self.hot.len() > limit && remaining > 0 {
+            remaining -= 1;
+            let tx = match self.order.pop_front() {
+                Some(tx) => tx,
+                None => break,
+            };
+            match self.hot.get(&tx) {
+                Some(transaction) if transaction.is_pending() => self.order.push_back(tx),
+                Some(&transaction) => {
+                    if let Err(error) = self.write_record(tx, Some(transaction)) {
+                        self.order.push_front(tx);
+                        return Err(error);
+                    }
+                    self.hot.remove(&tx);
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the record of a transaction from the file, if it was spilled.
+    fn read_record(&self, tx: TransactionId) -> Result<Option<Transaction>> {
+        if u64::from(tx) >= self.records {
+            return Ok(None);
+        }
+        let mut record = [0; RECORD_LENGTH];
+        read_exact_at(&self.file, &mut record, offset(tx))?;
+        decode(&record)
+    }
+
+    /// Reads a chunk of up to `CHUNK_RECORDS` records from the file, starting at the record of
+    /// the given ID, and returns the transactions they hold.
+    fn read_chunk(&self, first: u64) -> Vec<Result<(TransactionId, Transaction)>> {
+        let count = (self.records - first).min(CHUNK_RECORDS as u64) as usize;
+        let mut chunk = vec![0; count * RECORD_LENGTH];
+        if let Err(error) = read_exact_at(&self.file, &mut chunk, first * RECORD_LENGTH as u64) {
+            return vec![Err(error)];
+        }
+        // The index of a record is always converted from a `TransactionId`.
+        let ids = (first..).map(|tx| tx as TransactionId);
+        let records = chunk.chunks(RECORD_LENGTH).map(decode);
+        ids.zip(records)
+            .filter_map(|(tx, record)| record.transpose().map(|record| Ok((tx, record?))))
+            .collect()
+    }
+
+    /// Writes the record of a transaction to the file, or clears it if `None`.
+    fn write_record(&mut self, tx: TransactionId, transaction: Option<Transaction>) -> Result<()> {
+        let record = match transaction {
+            Some(transaction) => encode(&transaction),
+            None => [0; RECORD_LENGTH],
+        };
+        write_all_at(&self.file, &record, offset(tx))?;
+        self.records = self.records.max(u64::from(tx) + 1);
+        Ok(())
+    }
+}
+
+/// Returns the offset of the record of a transaction in the file.
+fn offset(tx: TransactionId) -> u64 {
+    u64::from(tx) * RECORD_LENGTH as u64
+}
+
+/// Encodes a transaction as a record: a non-zero byte (so that an empty record is all zeros),
+/// followed by its byte representation.
+fn encode(transaction: &Transaction) -> [u8; RECORD_LENGTH] {
+    let mut record = [0; RECORD_LENGTH];
+    record[0] = 1;
+    record[1..].copy_from_slice(&transaction.to_bytes());
+    record
+}
+
+/// Decodes a record written by `encode`, or returns `None` if it's empty.
+///
+/// Returns an `Error` if the record is invalid (i.e. the file was modified by another process).
+fn decode(record: &[u8]) -> Result<Option<Transaction>> {
+    if record[0] == 0 {
+        return Ok(None);
+    }
+    let mut bytes = [0; Transaction::ENCODED_LENGTH];
+    bytes.copy_from_slice(&record[1..RECORD_LENGTH]);
+    match Transaction::from_bytes(&bytes) {
+        Some(transaction) => Ok(Some(transaction)),
+        None => Err(Error::new(
+            ErrorKind::InvalidData,
+            "invalid transaction record",
+        )),
+    }
+}
+
+/// Reads exactly enough bytes to fill the buffer from the given offset of a file, regardless of
+/// its cursor (so that concurrent reads through shared references don't interfere).
+///
+/// The bytes past the end of the file are read as zeros (i.e. empty records).
+fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> Result<()> {
+    while !buffer.is_empty() {
+        match read_at(file, buffer, offset) {
+            Ok(0) => {
+                buffer.iter_mut().for_each(|byte| *byte = 0);
+                return Ok(());
+            }
+            Ok(n) => {
+                buffer = &mut buffer[n..];
+                offset += n as u64;
+            }
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+/// Writes the whole buffer at the given offset of a file, regardless of its cursor.
+fn write_all_at(file: &File, mut buffer: &[u8], mut offset: u64) -> Result<()> {
+    while !buffer.is_empty() {
+        match write_at(file, buffer, offset) {
+            Ok(0) => return Err(Error::new(ErrorKind::WriteZero, "failed to write record")),
+            Ok(n) => {
+                buffer = &buffer[n..];
+                offset += n as u64;
+            }
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buffer, offset)
+}
+
+#[cfg(unix)]
+fn write_at(file: &File, buffer: &[u8], offset: u64) -> Result<usize> {
+    std::os::unix::fs::FileExt::write_at(file, buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buffer, offset)
+}
+
+#[cfg(windows)]
+fn write_at(file: &File, buffer: &[u8], offset: u64) -> Result<usize> {
+    std::os::windows::fs::FileExt::seek_write(file, buffer, offset)
+}
@@ -1,23 +1,39 @@
-use crate::{Amount, Client, ClientId, Result, TransactionError, TransactionId, TransactionState};
-use crate::{SpecialTransaction, SpecialTransactionKind, Transaction, TransactionKind};
+use crate::{precision, snapshot, Amount, Balance, Client, ClientId, CreditLimit, Currency};
+use crate::{BatchError, CreditLimitChange, CrossClientDisputePolicy, DisputeWindow, Entry};
+use crate::{Event, Expiry, FeePolicy, Fees, HashMapStorage, Journal, Operation, OperationKind};
+use crate::{NegativeBalanceDisputePolicy, ParseAmountError, Receipt, Rejection, Result};
+use crate::{Settlement, SettlementKind, SpecialTransaction, SpecialTransactionKind};
+use crate::{StatusChange, Storage};
+use crate::{Transaction, TransactionError, TransactionId, TransactionKind, TransactionState};
+use crate::{TransactionView, WithdrawalDisputePolicy, DEFAULT_PRECISION, MAX_PRECISION};
 use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
 use TransactionError::*;
 use TransactionId as Tx;
 
-type ClientMap = HashMap<ClientId, Client>;
-type TransactionMap = HashMap<Tx, Transaction>;
+type Observer = Box<dyn FnMut(&Event) + Send>;
+
+/// The funds moved by a transaction, and the client account it was applied to.
+#[derive(Clone, Copy)]
+struct Movement {
+    client: ClientId,
+    currency: Currency,
+    amount: Balance,
+    fee: Balance,
+}
 
 /******************************************
  *               PUBLIC API               *
  ******************************************/
 
-/// A transaction engine.
-pub struct TransactionEngine {
-    // The `clients` field could also be implemented with a `Vec<Option<Client>>` with a fixed
-    // capacity of `ClientId::MAX as usize`. The `ClientId` itself would be the index.
-    // This would require 24 * 65,536 = 1,572,864 bytes of RAM on 64-bit machines but could
-    // be more efficient for most operations. However, the `iter_accounts` method would probably
-    // be slower to skip non-existent accounts. These are guesses and should be benchmarked.
+/// A transaction engine, which stores its client accounts and cached transactions in a
+/// `Storage` backend (`HashMapStorage` by default).
+pub struct TransactionEngine<S: Storage = HashMapStorage> {
+    // The client accounts and transactions are stored in hash maps by default, but the
+    // `VecStorage` backend indexes them by ID instead, which is much faster (see the benchmark
+    // in the README). However, its `iter_accounts` and `iter_transactions` methods are slower
+    // to skip non-existent entries, and its memory usage depends on the largest IDs.
     // Alternatively, other hash map implementations (e.g. `hashbrown::HashMap`) could be faster.
     //
     // Finally, if a transaction cannot be disputed by a different client ID, the `transactions`
@@ -26,57 +42,864 @@ pub struct TransactionEngine {
     // we could split them into multiple maps to avoid storing the `TransactionKind` and
     // `TransactionState` for each transaction. In the case, only the balance would need to be
     // stored per transaction.
-    clients: ClientMap,
-    transactions: TransactionMap,
+    storage: S,
+    journal: Option<Journal>,
+    journal_offset: u64,
+    observers: Vec<Observer>,
+    dispute_window: Option<DisputeWindow>,
+    expiry: Expiry,
+    precisions: HashMap<Currency, u32>,
+    fees: Fees,
+    cross_client_disputes: CrossClientDisputePolicy,
+    withdrawal_disputes: WithdrawalDisputePolicy,
+    negative_balance_disputes: NegativeBalanceDisputePolicy,
 }
 
 impl TransactionEngine {
-    /// Constructs a new transaction engine with no history of client accounts or transactions.
+    /// Constructs a new transaction engine with no history of client accounts or transactions,
+    /// stored in a `HashMapStorage`.
     pub fn init() -> Self {
-        Self {
-            clients: HashMap::new(),
-            transactions: HashMap::new(),
+        Self::from_storage(HashMapStorage::init())
+    }
+}
+
+impl<S: Storage> TransactionEngine<S> {
+    /// Constructs a new transaction engine with no history of client accounts or transactions,
+    /// stored in the given storage backend (which is cleared first).
+    ///
+    /// Returns an `Error` if the storage can't be cleared.
+    ///
+    /// ## Example
+    /// ```
+    /// use transaction_engine::{TransactionEngine, VecStorage};
+    ///
+    /// let mut engine = TransactionEngine::with_storage(VecStorage::init()).unwrap();
+    /// engine.deposit(1, 1, 1_0000).unwrap();
+    /// engine.withdrawal(1, 2, 0_2500).unwrap();
+    ///
+    /// assert_eq!(0_7500, engine.get_account(1).unwrap().get_total_balance());
+    /// ```
+    pub fn with_storage(mut storage: S) -> std::io::Result<Self> {
+        storage.clear()?;
+        Ok(Self::from_storage(storage))
+    }
+
+    /// Sets the dispute window after which transactions expire, or `None` (the default)
+    /// to never expire them.
+    ///
+    /// An expired transaction is removed from the cache, which bounds the memory usage of the
+    /// engine. A dispute referencing it is rejected with `DisputeWindowExpired`. If it was
+    /// already disputed when it expired, it can still be resolved or charged back, and it's
+    /// removed afterwards. Its ID still can't be reused by a new transaction.
+    ///
+    /// Only the transactions accepted after this method is called are subject to the window.
+    pub fn set_dispute_window(&mut self, window: Option<DisputeWindow>) {
+        self.dispute_window = window;
+    }
+
+    /// Returns the dispute window after which transactions expire, if any.
+    pub fn get_dispute_window(&self) -> Option<DisputeWindow> {
+        self.dispute_window
+    }
+
+    /// Sets the fee policy consulted on every deposit, withdrawal, transfer, authorization,
+    /// capture, void, dispute, resolve, and chargeback, or `None` (the default) to charge no fee.
+    ///
+    /// The fee is charged to the client account in the currency of the transaction, as part of
+    /// the same atomic update: if the transaction is rejected, no fee is charged. The fee of a
+    /// withdrawal, transfer, or authorization must fit within the credit limit along with its
+    /// amount. Any other fee is charged even if the available balance becomes negative.
+    ///
+    /// ## Example
+    /// ```
+    /// use transaction_engine::{StandardFeePolicy, TransactionEngine};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.set_fee_policy(Some(Box::new(StandardFeePolicy {
+    ///     withdrawal_fixed: 0_5000,
+    ///     withdrawal_rate: 100, // 1%
+    ///     chargeback_flat: 15_0000,
+    /// })));
+    /// engine.set_fee_account(Some(0));
+    ///
+    /// engine.deposit(1, 1, 100_0000).unwrap();
+    /// engine.withdrawal(1, 2, 50_0000).unwrap();
+    ///
+    /// assert_eq!(49_0000, engine.get_account(1).unwrap().get_total_balance());
+    /// assert_eq!(1_0000, engine.get_account(0).unwrap().get_total_balance());
+    /// ```
+    pub fn set_fee_policy(&mut self, policy: Option<Box<dyn FeePolicy>>) {
+        self.fees.policy = policy;
+    }
+
+    /// Sets the client account that the fees are credited to (e.g. a house account),
+    /// or `None` (the default) to only debit them from the client accounts.
+    ///
+    /// The fee account is created when the first fee is credited to it, and it's credited
+    /// regardless of its status, unless it's closed (in which case transactions with a fee
+    /// are rejected). A transaction of the fee account itself pays its fees to itself.
+    pub fn set_fee_account(&mut self, client: Option<ClientId>) {
+        self.fees.account = client;
+    }
+
+    /// Returns the client account that the fees are credited to, if any.
+    pub fn get_fee_account(&self) -> Option<ClientId> {
+        self.fees.account
+    }
+
+    /// Sets how a dispute, resolve, or chargeback whose client ID differs from the client ID
+    /// of the transaction it references is handled (rejected with `WrongClientId` by default).
+    ///
+    /// ## Example
+    /// ```
+    /// use transaction_engine::{CrossClientDisputePolicy, TransactionEngine};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.set_cross_client_dispute_policy(CrossClientDisputePolicy::ChargeOriginalClient);
+    /// engine.deposit(1, 1, 100).unwrap();
+    /// engine.dispute(2, 1).unwrap();
+    ///
+    /// assert_eq!(100, engine.get_account(1).unwrap().get_held_balance());
+    /// assert!(engine.get_account(2).is_none());
+    /// ```
+    pub fn set_cross_client_dispute_policy(&mut self, policy: CrossClientDisputePolicy) {
+        self.cross_client_disputes = policy;
+    }
+
+    /// Returns how cross-client disputes are handled.
+    pub fn get_cross_client_dispute_policy(&self) -> CrossClientDisputePolicy {
+        self.cross_client_disputes
+    }
+
+    /// Sets how a dispute of a withdrawal (or of a captured authorization) is handled
+    /// (`WithdrawalDisputePolicy::NoHold` by default).
+    ///
+    /// A withdrawal is resolved or charged back according to the policy it was disputed with,
+    /// even if the policy was changed since then.
+    ///
+    /// ## Example
+    /// ```
+    /// use transaction_engine::{TransactionEngine, WithdrawalDisputePolicy};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.set_withdrawal_dispute_policy(WithdrawalDisputePolicy::HoldCredit);
+    /// engine.deposit(1, 1, 100).unwrap();
+    /// engine.withdrawal(1, 2, 40).unwrap();
+    /// engine.dispute(1, 2).unwrap();
+    ///
+    /// let account = engine.get_account(1).unwrap();
+    /// assert_eq!(60, account.get_available_balance());
+    /// assert_eq!(40, account.get_held_balance());
+    /// assert_eq!(100, account.get_total_balance());
+    /// ```
+    pub fn set_withdrawal_dispute_policy(&mut self, policy: WithdrawalDisputePolicy) {
+        self.withdrawal_disputes = policy;
+    }
+
+    /// Returns how withdrawal disputes are handled.
+    pub fn get_withdrawal_dispute_policy(&self) -> WithdrawalDisputePolicy {
+        self.withdrawal_disputes
+    }
+
+    /// Sets how a dispute of a deposit whose amount is greater than the available balance
+    /// is handled (`NegativeBalanceDisputePolicy::Allow` by default).
+    ///
+    /// ## Example
+    /// ```
+    /// use transaction_engine::{NegativeBalanceDisputePolicy, TransactionEngine};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.set_negative_balance_dispute_policy(NegativeBalanceDisputePolicy::CapHold);
+    /// engine.deposit(1, 1, 100).unwrap();
+    /// engine.withdrawal(1, 2, 70).unwrap();
+    /// engine.dispute(1, 1).unwrap();
+    ///
+    /// let account = engine.get_account(1).unwrap();
+    /// assert_eq!(0, account.get_available_balance());
+    /// assert_eq!(30, account.get_held_balance());
+    /// assert_eq!(70, account.get_shortfall());
+    /// ```
+    pub fn set_negative_balance_dispute_policy(&mut self, policy: NegativeBalanceDisputePolicy) {
+        self.negative_balance_disputes = policy;
+    }
+
+    /// Returns how disputes that exceed the available balance are handled.
+    pub fn get_negative_balance_dispute_policy(&self) -> NegativeBalanceDisputePolicy {
+        self.negative_balance_disputes
+    }
+
+    /// Sets the number of decimal places of a currency (`DEFAULT_PRECISION` by default).
+    ///
+    /// Amounts and balances are always expressed in the smallest unit of their currency,
+    /// so the precision only affects how they're parsed from and formatted to decimal strings.
+    /// Therefore, it should be set before any transaction in that currency is processed.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the precision is greater than `MAX_PRECISION`.
+    pub fn set_precision(&mut self, currency: Currency, precision: u32) {
+        assert!(precision <= MAX_PRECISION, "precision is too large");
+        self.precisions.insert(currency, precision);
+    }
+
+    /// Returns the number of decimal places of a currency.
+    pub fn get_precision(&self, currency: Currency) -> u32 {
+        let precision = self.precisions.get(&currency).copied();
+        precision.unwrap_or(DEFAULT_PRECISION)
+    }
+
+    /// Attempts to parse a decimal amount into the smallest unit of a currency.
+    ///
+    /// Returns an `Error` if the amount has more decimal places than the currency allows,
+    /// instead of silently truncating it.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use transaction_engine::{Currency, ParseAmountError, TransactionEngine};
+    ///
+    /// let jpy: Currency = "JPY".parse().unwrap();
+    /// let mut engine = TransactionEngine::init();
+    /// engine.set_precision(jpy, 0);
+    ///
+    /// assert_eq!(Ok(1_5000), engine.parse_amount(Currency::default(), "1.5"));
+    /// assert_eq!(Ok(150), engine.parse_amount(jpy, "150.00"));
+    /// assert_eq!(Err(ParseAmountError::TooPrecise(0)), engine.parse_amount(jpy, "1.5"));
+    /// ```
+    pub fn parse_amount(
+        &self,
+        currency: Currency,
+        amount: &str,
+    ) -> std::result::Result<Amount, ParseAmountError> {
+        precision::parse_amount(amount, self.get_precision(currency))
+    }
+
+    /// Formats a balance in the smallest unit of a currency as a decimal string,
+    /// with exactly as many decimal places as the currency.
+    pub fn format_balance(&self, currency: Currency, balance: Balance) -> String {
+        precision::format_balance(balance, self.get_precision(currency))
+    }
+
+    /// Registers an observer that is called with an `Event` after every processed transaction,
+    /// whether it was accepted or rejected. Observers are called in the order they were added.
+    ///
+    /// Transactions replayed from the journal don't emit any event.
+    ///
+    /// ## Example
+    /// ```
+    /// use std::sync::mpsc::channel;
+    /// use transaction_engine::{Event, TransactionEngine, TransactionError};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// let (sender, receiver) = channel();
+    /// engine.subscribe(move |event| sender.send(*event).unwrap());
+    ///
+    /// engine.deposit(1, 1, 1_0000).unwrap();
+    /// engine.withdrawal(1, 2, 2_0000).unwrap_err();
+    ///
+    /// match receiver.recv().unwrap() {
+    ///     Event::DepositAccepted(receipt) => assert_eq!(1_0000, receipt.available),
+    ///     event => panic!("unexpected event: {:?}", event),
+    /// }
+    /// match receiver.recv().unwrap() {
+    ///     Event::Rejected(rejection) => {
+    ///         assert_eq!(TransactionError::InsufficientAvailableFunds, rejection.error)
+    ///     }
+    ///     event => panic!("unexpected event: {:?}", event),
+    /// }
+    /// ```
+    pub fn subscribe<F: FnMut(&Event) + Send + 'static>(&mut self, observer: F) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Restores the complete state of the transaction engine from the snapshot at the given
+    /// path, discarding its current state.
+    ///
+    /// This should be called right after initializing the transaction engine (and setting its
+    /// dispute window, if any). If the journal is opened afterwards, only the transactions
+    /// recorded after the snapshot was saved are replayed, which restores exactly the same
+    /// state as before the process was stopped.
+    ///
+    /// Returns an `Error` if the snapshot can't be read or if it has an unsupported version,
+    /// or if the storage fails. In the latter case, the state of the engine is incomplete.
+    pub fn load_snapshot<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let snapshot = snapshot::read(path)?;
+        self.storage.clear()?;
+        for (id, client) in snapshot.clients {
+            self.storage.insert_client(id, client);
         }
+        for (tx, transaction) in snapshot.transactions {
+            self.storage.insert_transaction(tx, transaction)?;
+        }
+        self.expiry = snapshot.expiry;
+        self.journal_offset = snapshot.journal_offset;
+        Ok(())
+    }
+
+    /// Saves the complete state of the transaction engine to a snapshot at the given path.
+    ///
+    /// The snapshot also records the current position in the journal (if it's open),
+    /// so that the journal can be replayed from that position after restoring it.
+    ///
+    /// Returns an `Error` if the snapshot can't be written, or if the storage fails.
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let journal_offset = match &self.journal {
+            Some(journal) => journal.len(),
+            None => self.journal_offset,
+        };
+        let clients = self.storage.iter_clients();
+        let transactions = self.storage.iter_transactions();
+        snapshot::write(path, journal_offset, &self.expiry, clients, transactions)
+    }
+
+    /// Replays the journal at the given path, then records every subsequent accepted
+    /// transaction in it. The journal file is created if it doesn't already exist.
+    ///
+    /// This should be called right after initializing the transaction engine (or restoring
+    /// a snapshot), so that its state is rebuilt exactly as it was before the process was
+    /// stopped (or crashed). Once the journal is open, a transaction is only acknowledged
+    /// with `Ok` after it has been appended to the journal.
+    ///
+    /// Only transactions are recorded in the journal, not the configuration of the engine
+    /// (e.g. its dispute window, fee policy and account, dispute policies, and precisions).
+    /// Therefore, the configuration must be set before opening the journal, exactly as it was
+    /// when the journal was written. Note that the time of a replayed transaction is the time
+    /// it was replayed.
+    ///
+    /// Returns an `Error` if the journal can't be read or written, or if one of its
+    /// transactions fails to replay (e.g. the journal was written by a different engine).
+    pub fn open_journal<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        Journal::replay(&path, self.journal_offset, |entry| {
+            let message = match entry {
+                Entry::Operation(operation) => match self.execute(operation) {
+                    Ok(_) => return Ok(()),
+                    Err(e) => format!("failed to replay {:?}: {}", operation, e),
+                },
+                Entry::Batch(operations) => match self.execute_batch(&operations, None) {
+                    Ok(_) => return Ok(()),
+                    Err(e) => format!("failed to replay batch: {}", e),
+                },
+            };
+            Err(Error::new(ErrorKind::InvalidData, message))
+        })?;
+        self.journal = Some(Journal::open(&path)?);
+        Ok(())
     }
 
     /// Returns a single client account by ID.
     pub fn get_account(&self, client: ClientId) -> Option<&Client> {
-        self.clients.get(&client)
+        self.storage.get_client(client)
     }
 
     /// Returns an iterator over all client accounts in arbitrary order.
-    pub fn iter_accounts(&self) -> impl Iterator<Item = (ClientId, &Client)> {
-        self.clients.iter().map(|(&id, client)| (id, client))
+    pub fn iter_accounts(&self) -> impl Iterator<Item = (ClientId, &Client)> + '_ {
+        self.storage.iter_clients()
+    }
+
+    /// Returns a single transaction by ID.
+    ///
+    /// Only regular transactions (i.e. deposits, withdrawals, transfers, and authorizations)
+    /// that were processed successfully can be returned, as long as they're cached (see
+    /// `set_dispute_window`).
+    ///
+    /// Returns an `Error` if the storage fails.
+    pub fn get_transaction(&self, tx: Tx) -> std::io::Result<Option<TransactionView>> {
+        let transaction = self.storage.get_transaction(tx)?;
+        Ok(transaction.map(|transaction| TransactionView::new(tx, transaction)))
+    }
+
+    /// Returns an iterator over all transactions in arbitrary order.
+    ///
+    /// It yields an `Error` for the transactions that can't be read if the storage fails.
+    pub fn iter_transactions(&self) -> impl Iterator<Item = std::io::Result<TransactionView>> + '_ {
+        let iter = self.storage.iter_transactions();
+        iter.map(|transaction| {
+            let (tx, transaction) = transaction?;
+            Ok(TransactionView::new(tx, transaction))
+        })
     }
 
-    /// Attempts to process a single deposit transaction.
+    /// Returns an iterator over all transactions of a single client in arbitrary order.
+    ///
+    /// Note that this method goes through the transactions of *all* clients,
+    /// so it shouldn't be called on a hot path.
+    /// Like `iter_transactions`, it yields an `Error` if the storage fails.
+    pub fn iter_client_transactions(
+        &self,
+        client: ClientId,
+    ) -> impl Iterator<Item = std::io::Result<TransactionView>> + '_ {
+        let iter = self.iter_transactions();
+        iter.filter(move |transaction| match transaction {
+            Ok(transaction) => transaction.get_client() == client,
+            Err(_) => true,
+        })
+    }
+
+    /// Returns an iterator over all transactions of a single client in arbitrary order,
+    /// optionally filtered by kind and/or by state.
+    ///
+    /// Note that this method goes through the transactions of *all* clients,
+    /// so it shouldn't be called on a hot path.
+    /// Like `iter_transactions`, it yields an `Error` if the storage fails.
+    ///
+    /// ## Example
+    /// ```
+    /// use transaction_engine::{TransactionEngine, TransactionKind, TransactionState};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.deposit(42, 1, 1_0000).unwrap();
+    /// engine.deposit(42, 2, 2_0000).unwrap();
+    /// engine.dispute(42, 2).unwrap();
+    ///
+    /// // Which deposits of client 42 are currently disputed?
+    /// let kind = Some(TransactionKind::Deposit);
+    /// let state = Some(TransactionState::Disputed);
+    /// let disputed = engine.filter_client_transactions(42, kind, state);
+    /// let disputed = disputed.collect::<std::io::Result<Vec<_>>>().unwrap();
+    /// assert_eq!(1, disputed.len());
+    /// assert_eq!(2, disputed[0].get_id());
+    /// assert_eq!(2_0000, disputed[0].get_amount());
+    /// ```
+    pub fn filter_client_transactions(
+        &self,
+        client: ClientId,
+        kind: Option<TransactionKind>,
+        state: Option<TransactionState>,
+    ) -> impl Iterator<Item = std::io::Result<TransactionView>> + '_ {
+        self.iter_client_transactions(client)
+            .filter(move |transaction| match transaction {
+                Ok(transaction) => {
+                    (kind.is_none() || kind == Some(transaction.get_kind()))
+                        && (state.is_none() || state == Some(transaction.get_state()))
+                }
+                Err(_) => true,
+            })
+    }
+
+    /// Attempts to process a single deposit transaction in the default currency.
     pub fn deposit(&mut self, client: ClientId, tx: Tx, amount: Amount) -> Result<()> {
-        let transaction = Transaction::try_new_deposit(client, amount)?;
-        self.process_regular_transaction(tx, transaction)
+        self.deposit_in(client, tx, amount, Currency::default())
     }
 
-    /// Attempts to process a single withdrawal transaction.
+    /// Attempts to process a single withdrawal transaction in the default currency.
     pub fn withdrawal(&mut self, client: ClientId, tx: Tx, amount: Amount) -> Result<()> {
-        let transaction = Transaction::try_new_withdrawal(client, amount)?;
-        self.process_regular_transaction(tx, transaction)
+        self.withdrawal_in(client, tx, amount, Currency::default())
+    }
+
+    /// Attempts to process a single deposit transaction in the given currency.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use transaction_engine::{Currency, TransactionEngine, TransactionError};
+    ///
+    /// let usd: Currency = "USD".parse().unwrap();
+    /// let btc: Currency = "BTC".parse().unwrap();
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.deposit_in(1, 1, 5_0000, usd).unwrap();
+    /// let result = engine.withdrawal_in(1, 2, 1_0000, btc);
+    /// assert_eq!(Err(TransactionError::InsufficientAvailableFunds), result);
+    ///
+    /// let client = engine.get_account(1).unwrap();
+    /// assert_eq!(5_0000, client.get_wallet(usd).unwrap().get_total_balance());
+    /// assert!(client.get_wallet(btc).is_none());
+    /// ```
+    pub fn deposit_in(
+        &mut self,
+        client: ClientId,
+        tx: Tx,
+        amount: Amount,
+        currency: Currency,
+    ) -> Result<()> {
+        self.process(Operation::Deposit {
+            client,
+            tx,
+            amount,
+            currency,
+        })
+    }
+
+    /// Attempts to process a single withdrawal transaction in the given currency.
+    pub fn withdrawal_in(
+        &mut self,
+        client: ClientId,
+        tx: Tx,
+        amount: Amount,
+        currency: Currency,
+    ) -> Result<()> {
+        self.process(Operation::Withdrawal {
+            client,
+            tx,
+            amount,
+            currency,
+        })
+    }
+
+    /// Attempts to process a single transfer transaction in the default currency.
+    pub fn transfer(&mut self, from: ClientId, to: ClientId, tx: Tx, amount: Amount) -> Result<()> {
+        self.transfer_in(from, to, tx, amount, Currency::default())
+    }
+
+    /// Attempts to process a single transfer transaction in the given currency.
+    ///
+    /// The amount is debited from the `from` client account and credited to the `to` client
+    /// account (which is created if it doesn't already exist) atomically: if an `Error` is
+    /// returned, neither account was modified. Both accounts must be active.
+    ///
+    /// A transfer can only be disputed by its sender (i.e. `from`). The disputed amount is then
+    /// held in the recipient's account, like a disputed deposit. A chargeback removes it from
+    /// the recipient's account, credits it back to the sender's account, and locks the sender's
+    /// account. The recipient's account only needs to not be closed for these operations.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use transaction_engine::{TransactionEngine, TransactionError};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.deposit(1, 1, 5_0000).unwrap();
+    /// engine.transfer(1, 2, 2, 2_0000).unwrap();
+    /// let result = engine.transfer(1, 2, 3, 4_0000);
+    /// assert_eq!(Err(TransactionError::InsufficientAvailableFunds), result);
+    ///
+    /// engine.dispute(1, 2).unwrap();
+    /// let recipient = engine.get_account(2).unwrap();
+    /// assert_eq!(0_0000, recipient.get_available_balance());
+    /// assert_eq!(2_0000, recipient.get_held_balance());
+    /// ```
+    pub fn transfer_in(
+        &mut self,
+        from: ClientId,
+        to: ClientId,
+        tx: Tx,
+        amount: Amount,
+        currency: Currency,
+    ) -> Result<()> {
+        self.process(Operation::Transfer {
+            client: from,
+            tx,
+            to,
+            amount,
+            currency,
+        })
+    }
+
+    /// Attempts to process a single authorization transaction in the default currency.
+    pub fn authorize(&mut self, client: ClientId, tx: Tx, amount: Amount) -> Result<()> {
+        self.authorize_in(client, tx, amount, Currency::default())
+    }
+
+    /// Attempts to process a single authorization transaction in the given currency.
+    ///
+    /// The amount is held in the client account (i.e. the available balance decreases and
+    /// the held balance increases) until the authorization is captured or voided.
+    /// Until then, it can't be disputed.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use transaction_engine::{TransactionEngine, TransactionError};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.deposit(1, 1, 5_0000).unwrap();
+    /// engine.authorize(1, 2, 3_0000).unwrap();
+    /// assert_eq!(3_0000, engine.get_account(1).unwrap().get_held_balance());
+    ///
+    /// engine.capture_partial(1, 2, 1_0000).unwrap();
+    /// let result = engine.void(1, 2);
+    /// assert_eq!(Err(TransactionError::AuthorizationAlreadyCaptured), result);
+    ///
+    /// let client = engine.get_account(1).unwrap();
+    /// assert_eq!(4_0000, client.get_available_balance());
+    /// assert_eq!(0_0000, client.get_held_balance());
+    /// ```
+    pub fn authorize_in(
+        &mut self,
+        client: ClientId,
+        tx: Tx,
+        amount: Amount,
+        currency: Currency,
+    ) -> Result<()> {
+        self.process(Operation::Authorize {
+            client,
+            tx,
+            amount,
+            currency,
+        })
+    }
+
+    /// Attempts to process a single capture of the whole amount of an authorization.
+    pub fn capture(&mut self, client: ClientId, tx: Tx) -> Result<()> {
+        let amount = None;
+        self.process(Operation::Capture { client, tx, amount })
+    }
+
+    /// Attempts to process a single capture of part of the amount of an authorization.
+    ///
+    /// The captured amount is debited from the client account, and the rest is released.
+    /// An authorization can only be captured once.
+    pub fn capture_partial(&mut self, client: ClientId, tx: Tx, amount: Amount) -> Result<()> {
+        let amount = Some(amount);
+        self.process(Operation::Capture { client, tx, amount })
+    }
+
+    /// Attempts to process a single void of an authorization, which releases its amount.
+    pub fn void(&mut self, client: ClientId, tx: Tx) -> Result<()> {
+        self.process(Operation::Void { client, tx })
     }
 
     /// Attempts to process a single dispute transaction.
+    ///
+    /// The whole undisputed amount of the referenced transaction is disputed.
     pub fn dispute(&mut self, client: ClientId, tx: Tx) -> Result<()> {
-        let transaction = SpecialTransaction::new_dispute(client, tx);
-        self.process_special_transaction(transaction)
+        let amount = None;
+        self.process(Operation::Dispute { client, tx, amount })
     }
 
     /// Attempts to process a single resolve transaction.
+    ///
+    /// The whole disputed amount of the referenced transaction is resolved.
     pub fn resolve(&mut self, client: ClientId, tx: Tx) -> Result<()> {
-        let transaction = SpecialTransaction::new_resolve(client, tx);
-        self.process_special_transaction(transaction)
+        let amount = None;
+        self.process(Operation::Resolve { client, tx, amount })
     }
 
     /// Attempts to process a single chargeback transaction.
+    ///
+    /// The whole disputed amount of the referenced transaction is charged back.
     pub fn chargeback(&mut self, client: ClientId, tx: Tx) -> Result<()> {
-        let transaction = SpecialTransaction::new_chargeback(client, tx);
-        self.process_special_transaction(transaction)
+        let amount = None;
+        self.process(Operation::Chargeback { client, tx, amount })
+    }
+
+    /// Attempts to process a single dispute transaction for part of the referenced amount.
+    ///
+    /// A transaction can be disputed several times, as long as the sum of the disputed and
+    /// reversed amounts doesn't exceed its amount. Otherwise, `InvalidDisputeAmount` is returned.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use transaction_engine::{TransactionEngine, TransactionState};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.deposit(1, 1, 10_0000).unwrap();
+    /// engine.dispute_partial(1, 1, 3_0000).unwrap();
+    /// engine.dispute_partial(1, 1, 2_0000).unwrap();
+    /// engine.chargeback_partial(1, 1, 4_0000).unwrap();
+    ///
+    /// let transaction = engine.get_transaction(1).unwrap().unwrap();
+    /// assert_eq!(TransactionState::Disputed, transaction.get_state());
+    /// assert_eq!(1_0000, transaction.get_disputed_amount());
+    /// assert_eq!(4_0000, transaction.get_reversed_amount());
+    /// ```
+    pub fn dispute_partial(&mut self, client: ClientId, tx: Tx, amount: Amount) -> Result<()> {
+        let amount = Some(amount);
+        self.process(Operation::Dispute { client, tx, amount })
+    }
+
+    /// Attempts to process a single resolve transaction for part of the disputed amount.
+    pub fn resolve_partial(&mut self, client: ClientId, tx: Tx, amount: Amount) -> Result<()> {
+        let amount = Some(amount);
+        self.process(Operation::Resolve { client, tx, amount })
+    }
+
+    /// Attempts to process a single chargeback transaction for part of the disputed amount.
+    pub fn chargeback_partial(&mut self, client: ClientId, tx: Tx, amount: Amount) -> Result<()> {
+        let amount = Some(amount);
+        self.process(Operation::Chargeback { client, tx, amount })
+    }
+
+    /// Attempts to unlock a client account that was locked by a chargeback.
+    pub fn unlock(&mut self, client: ClientId) -> Result<()> {
+        self.process(Operation::Unlock { client })
+    }
+
+    /// Attempts to freeze an active client account. All transactions referencing a frozen
+    /// account are rejected until it's unfrozen.
+    pub fn freeze(&mut self, client: ClientId) -> Result<()> {
+        self.process(Operation::Freeze { client })
+    }
+
+    /// Attempts to unfreeze a frozen client account.
+    pub fn unfreeze(&mut self, client: ClientId) -> Result<()> {
+        self.process(Operation::Unfreeze { client })
+    }
+
+    /// Attempts to close a client account. Only an empty account can be closed, i.e. one whose
+    /// available and total balances are zero in every currency, with no shortfall left by
+    /// a dispute (see `NegativeBalanceDisputePolicy`). Otherwise, `ClientAccountNotEmpty` is
+    /// returned. All transactions referencing a closed account are rejected forever.
+    pub fn close(&mut self, client: ClientId) -> Result<()> {
+        self.process(Operation::Close { client })
+    }
+
+    /// Attempts to set the credit limit of an existing client account in the given currency.
+    ///
+    /// The limit applies to withdrawals, transfers, and authorizations. Lowering it below the
+    /// current balances is accepted, but it prevents any further withdrawal until then.
+    /// Exceeding the overdraft returns `OverdraftLimitExceeded` (instead of the usual
+    /// `InsufficientAvailableFunds`), and going below the floor returns `TotalBalanceBelowFloor`.
+    /// Setting a limit in a currency the client account never used doesn't create its wallet
+    /// (see `Client::get_credit_limit`).
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use transaction_engine::{Currency, CreditLimit, TransactionEngine, TransactionError};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.deposit(1, 1, 1_0000).unwrap();
+    /// let limit = CreditLimit { overdraft: 5_0000, floor: Some(-3_0000) };
+    /// engine.set_credit_limit(1, Currency::default(), limit).unwrap();
+    ///
+    /// engine.withdrawal(1, 2, 3_0000).unwrap();
+    /// let result = engine.withdrawal(1, 3, 2_0000);
+    /// assert_eq!(Err(TransactionError::TotalBalanceBelowFloor), result);
+    /// assert_eq!(-2_0000, engine.get_account(1).unwrap().get_available_balance());
+    /// ```
+    pub fn set_credit_limit(
+        &mut self,
+        client: ClientId,
+        currency: Currency,
+        limit: CreditLimit,
+    ) -> Result<()> {
+        self.process(Operation::SetCreditLimit {
+            client,
+            currency,
+            limit,
+        })
+    }
+
+    /// Attempts to process a single operation, and returns its outcome as an `Event`: the new
+    /// balances of the client account if it was accepted, or the reason why it was rejected.
+    ///
+    /// This is the single entry point for any operation (e.g. deserialized from a CSV row or
+    /// a network message). It behaves exactly like the corresponding method (e.g. `deposit_in`
+    /// for `Operation::Deposit`): the operation is recorded in the journal if it's accepted,
+    /// and the observers are notified of the same event.
+    ///
+    /// ## Example
+    /// ```
+    /// use transaction_engine::{Currency, Event, Operation, TransactionEngine, TransactionError};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// let currency = Currency::default();
+    ///
+    /// let deposit = Operation::Deposit { client: 1, tx: 1, amount: 100, currency };
+    /// match engine.apply(deposit) {
+    ///     Event::DepositAccepted(receipt) => assert_eq!(100, receipt.available),
+    ///     event => panic!("unexpected event: {:?}", event),
+    /// }
+    ///
+    /// let withdrawal = Operation::Withdrawal { client: 1, tx: 2, amount: 150, currency };
+    /// let event = engine.apply(withdrawal);
+    /// assert!(!event.is_accepted());
+    /// assert_eq!(Some(TransactionError::InsufficientAvailableFunds), event.get_error());
+    /// ```
+    pub fn apply(&mut self, operation: Operation) -> Event {
+        let (_, event) = self.process_with_event(operation, true);
+        // The event is always built when it's requested.
+        event.expect("event wasn't built")
+    }
+
+    /// Attempts to apply a batch of operations atomically: either all of them are accepted,
+    /// or none of them is.
+    ///
+    /// The operations are applied in order. If one of them fails, every change made by the
+    /// previous ones to the client accounts and transactions is rolled back, and the returned
+    /// error reports which operation failed and why. An accepted batch is recorded in the
+    /// journal as a single entry, so that it's also replayed atomically.
+    ///
+    /// Observers are notified of every operation once the whole batch is accepted. Otherwise,
+    /// they're only notified of the rejection of the failed operation. Transactions don't
+    /// expire in the middle of a batch, only before it.
+    ///
+    /// ## Example
+    /// ```
+    /// use transaction_engine::{Currency, Operation, TransactionEngine, TransactionError};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// let currency = Currency::default();
+    /// let batch = [
+    ///     Operation::Deposit { client: 1, tx: 1, amount: 100, currency },
+    ///     Operation::Withdrawal { client: 1, tx: 2, amount: 150, currency },
+    /// ];
+    ///
+    /// let error = engine.batch(&batch).unwrap_err();
+    /// assert_eq!(1, error.index);
+    /// assert_eq!(TransactionError::InsufficientAvailableFunds, error.error);
+    /// assert!(engine.get_account(1).is_none());
+    /// ```
+    pub fn batch(&mut self, operations: &[Operation]) -> std::result::Result<(), BatchError> {
+        // Only build the events if there's at least one observer.
+        let mut events = Vec::new();
+        let result = if self.observers.is_empty() {
+            self.execute_batch(operations, None)
+        } else {
+            self.execute_batch(operations, Some(&mut events))
+        };
+
+        match result {
+            // Record the batch only if it was successful (and not empty).
+            Ok(()) if !operations.is_empty() => {
+                if let Some(journal) = &mut self.journal {
+                    // Just like for a single operation, the process *must* stop before
+                    // acknowledging the batch if the journal can't be written.
+                    journal
+                        .append(&Entry::Batch(operations.to_vec()))
+                        .expect("failed to write to journal");
+                }
+            }
+            Ok(()) => {}
+            Err(error) => {
+                events.clear();
+                if !self.observers.is_empty() {
+                    events.push(self.build_event(error.operation, Err(error.error)));
+                }
+            }
+        }
+
+        // Notify the observers.
+        for event in &events {
+            self.observers
+                .iter_mut()
+                .for_each(|observer| observer(event));
+        }
+
+        result
+    }
+
+    /// Checks whether an operation would be accepted in the current state of the transaction
+    /// engine, and returns the `Result` it would return, without modifying any client account
+    /// or transaction.
+    ///
+    /// The operation is executed and immediately rolled back (just like a failed batch),
+    /// so the state is left exactly as it was. It isn't recorded in the journal and doesn't
+    /// notify the observers. Transactions aren't expired beforehand.
+    ///
+    /// ## Example
+    /// ```
+    /// use transaction_engine::{Currency, Operation, TransactionEngine, TransactionError};
+    ///
+    /// let mut engine = TransactionEngine::init();
+    /// engine.deposit(1, 1, 100).unwrap();
+    ///
+    /// let currency = Currency::default();
+    /// let withdrawal = Operation::Withdrawal { client: 1, tx: 2, amount: 150, currency };
+    /// let dispute = Operation::Dispute { client: 1, tx: 1, amount: None };
+    /// assert_eq!(Err(TransactionError::InsufficientAvailableFunds), engine.dry_run(withdrawal));
+    /// assert_eq!(Ok(()), engine.dry_run(dispute));
+    /// assert_eq!(100, engine.get_account(1).unwrap().get_available_balance());
+    /// ```
+    pub fn dry_run(&mut self, operation: Operation) -> Result<()> {
+        let mut undo = Undo::new(self.expiry.checkpoint());
+        let result = undo
+            .record(self, operation)
+            .and_then(|_| self.execute_operation(operation));
+        undo.rollback(self)?;
+        result.map(|_| ())
     }
 }
 
@@ -84,82 +907,796 @@ impl TransactionEngine {
  *               PRIVATE API               *
  *******************************************/
 
-impl TransactionEngine {
-    fn process_regular_transaction(&mut self, tx: Tx, transaction: Transaction) -> Result<()> {
+impl<S: Storage> TransactionEngine<S> {
+    /// Constructs a new transaction engine around the given storage, without clearing it.
+    pub(crate) fn from_storage(storage: S) -> Self {
+        Self {
+            storage,
+            journal: None,
+            journal_offset: 0,
+            observers: Vec::new(),
+            dispute_window: None,
+            expiry: Expiry::default(),
+            precisions: HashMap::new(),
+            fees: Fees::default(),
+            cross_client_disputes: CrossClientDisputePolicy::Reject,
+            withdrawal_disputes: WithdrawalDisputePolicy::NoHold,
+            negative_balance_disputes: NegativeBalanceDisputePolicy::Allow,
+        }
+    }
+
+    pub(crate) fn process(&mut self, operation: Operation) -> Result<()> {
+        self.process_with_event(operation, false).0
+    }
+
+    /// Attempts to process a single operation, and builds its event if `event` is true or if
+    /// there's at least one observer to notify.
+    fn process_with_event(
+        &mut self,
+        operation: Operation,
+        event: bool,
+    ) -> (Result<()>, Option<Event>) {
+        let result = self.execute(operation);
+
+        // Record the operation only if it was successful. Otherwise, it would fail on replay.
+        if let (Ok(_), Some(journal)) = (result, &mut self.journal) {
+            // The in-memory state has already been modified at this point, so it's too late
+            // to return an error. If the journal can't be written, the process *must* stop
+            // before acknowledging the operation.
+            journal
+                .append(&Entry::Operation(operation))
+                .expect("failed to write to journal");
+        }
+
+        // Notify the observers, but only build the event if there's at least one of them
+        // (or if it's requested).
+        let event = if event || !self.observers.is_empty() {
+            Some(self.build_event(operation, result))
+        } else {
+            None
+        };
+        if let Some(event) = &event {
+            self.observers
+                .iter_mut()
+                .for_each(|observer| observer(event));
+        }
+
+        (result.map(|_| ()), event)
+    }
+
+    /// Attempts to execute a single operation without expiring transactions first or recording
+    /// it in the journal, and builds its event if `event` is true.
+    pub(crate) fn execute_with_event(
+        &mut self,
+        operation: Operation,
+        event: bool,
+    ) -> (Result<()>, Option<Event>) {
+        let result = self.execute_operation(operation);
+        let event = if event {
+            Some(self.build_event(operation, result))
+        } else {
+            None
+        };
+        (result.map(|_| ()), event)
+    }
+
+    /// Removes a client account (if it exists), so that another engine can borrow it.
+    pub(crate) fn take_account(&mut self, client: ClientId) -> Option<Client> {
+        let account = self.storage.get_client(client).cloned();
+        self.storage.remove_client(client);
+        account
+    }
+
+    /// Inserts a client account borrowed from another engine, or removes it if `None`.
+    pub(crate) fn put_account(&mut self, client: ClientId, account: Option<Client>) {
+        match account {
+            Some(account) => self.storage.insert_client(client, account),
+            None => self.storage.remove_client(client),
+        }
+    }
+
+    fn build_event(&self, operation: Operation, result: Result<Option<Movement>>) -> Event {
+        let client = operation.client();
+        let tx = operation.tx();
+
+        let movement = match result {
+            Ok(movement) => movement,
+            Err(error) => {
+                return Event::Rejected(Rejection {
+                    kind: operation.kind(),
+                    client,
+                    tx,
+                    amount: operation.amount(),
+                    currency: operation.currency(),
+                    error,
+                })
+            }
+        };
+
+        // The client account exists after a successful operation, and so does the account the
+        // transaction was applied to (if any).
+        let (tx, movement) = match (tx, movement) {
+            (Some(tx), Some(movement)) => (tx, movement),
+            _ => {
+                if let Operation::SetCreditLimit {
+                    currency, limit, ..
+                } = operation
+                {
+                    let change = CreditLimitChange {
+                        client,
+                        currency,
+                        limit,
+                    };
+                    return Event::CreditLimitChanged(change);
+                }
+                let account = self.storage.get_client(client);
+                let status = account.expect("client account doesn't exist").get_status();
+                return Event::StatusChanged(StatusChange { client, status });
+            }
+        };
+        // That account also has a wallet in the currency of the transaction.
+        let account = self.storage.get_client(movement.client);
+        let account = account.expect("client account doesn't exist");
+        let wallet = account
+            .get_wallet(movement.currency)
+            .expect("wallet doesn't exist");
+        let receipt = Receipt {
+            client: movement.client,
+            tx,
+            amount: movement.amount as Amount,
+            fee: movement.fee as Amount,
+            currency: movement.currency,
+            available: wallet.get_available_balance(),
+            held: wallet.get_held_balance(),
+            total: wallet.get_total_balance(),
+        };
+
+        match operation.kind() {
+            OperationKind::Deposit => Event::DepositAccepted(receipt),
+            OperationKind::Withdrawal => Event::WithdrawalAccepted(receipt),
+            OperationKind::Transfer => Event::TransferAccepted(receipt),
+            OperationKind::Authorize => Event::FundsAuthorized(receipt),
+            OperationKind::Capture => Event::AuthorizationCaptured(receipt),
+            OperationKind::Void => Event::AuthorizationVoided(receipt),
+            OperationKind::Dispute => Event::FundsHeld(receipt),
+            OperationKind::Resolve => Event::DisputeResolved(receipt),
+            OperationKind::Chargeback => Event::AccountLocked(receipt),
+            _ => unreachable!("operation should have a transaction id"),
+        }
+    }
+
+    /// Attempts to execute a single operation, after expiring transactions (if needed).
+    ///
+    /// Returns the currency and amount of the transaction created by this operation, or the
+    /// part of the referenced transaction's amount that was disputed, resolved, or charged back
+    /// (if any), along with the fee charged for it.
+    fn execute(&mut self, operation: Operation) -> Result<Option<Movement>> {
+        self.expire_transactions()?;
+        self.execute_operation(operation)
+    }
+
+    /// Attempts to execute a batch of operations atomically, without expiring transactions
+    /// in the middle of it. If `events` is provided, the event of every accepted operation is
+    /// pushed into it.
+    ///
+    /// Returns an `Error` if any operation fails. In that case, every change made by the
+    /// previous operations is rolled back.
+    fn execute_batch(
+        &mut self,
+        operations: &[Operation],
+        mut events: Option<&mut Vec<Event>>,
+    ) -> std::result::Result<(), BatchError> {
+        // If the storage fails while expiring transactions, the batch is rejected before its
+        // first operation.
+        if let (Err(error), Some(&operation)) = (self.expire_transactions(), operations.first()) {
+            return Err(BatchError {
+                index: 0,
+                operation,
+                error,
+            });
+        }
+        let mut undo = Undo::new(self.expiry.checkpoint());
+        for (index, &operation) in operations.iter().enumerate() {
+            let result = undo
+                .record(self, operation)
+                .and_then(|_| self.execute_operation(operation));
+            match result {
+                Ok(movement) => {
+                    if let Some(events) = &mut events {
+                        events.push(self.build_event(operation, Ok(movement)));
+                    }
+                }
+                Err(error) => {
+                    // If the storage fails while rolling back, the batch fails because of it.
+                    let error = match undo.rollback(self) {
+                        Ok(()) => error,
+                        Err(failure) => failure,
+                    };
+                    return Err(BatchError {
+                        index,
+                        operation,
+                        error,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Attempts to execute a single operation without expiring transactions first.
+    fn execute_operation(&mut self, operation: Operation) -> Result<Option<Movement>> {
+        let kind = operation.kind();
+        match operation {
+            Operation::Deposit {
+                client,
+                tx,
+                amount,
+                currency,
+            } => {
+                let transaction = Transaction::try_new_deposit(client, currency, amount)?;
+                self.process_regular_transaction(kind, tx, transaction)
+            }
+            Operation::Withdrawal {
+                client,
+                tx,
+                amount,
+                currency,
+            } => {
+                let transaction = Transaction::try_new_withdrawal(client, currency, amount)?;
+                self.process_regular_transaction(kind, tx, transaction)
+            }
+            Operation::Transfer {
+                client,
+                tx,
+                to,
+                amount,
+                currency,
+            } => {
+                let transaction = Transaction::try_new_transfer(client, to, currency, amount)?;
+                self.process_regular_transaction(kind, tx, transaction)
+            }
+            Operation::Authorize {
+                client,
+                tx,
+                amount,
+                currency,
+            } => {
+                let transaction = Transaction::try_new_authorization(client, currency, amount)?;
+                self.process_regular_transaction(kind, tx, transaction)
+            }
+            Operation::Capture { client, tx, amount } => {
+                let settlement = Settlement::try_new_capture(client, tx, amount)?;
+                self.process_settlement(kind, settlement)
+            }
+            Operation::Void { client, tx } => {
+                let settlement = Settlement::new_void(client, tx);
+                self.process_settlement(kind, settlement)
+            }
+            Operation::Dispute { client, tx, amount } => {
+                let transaction = SpecialTransaction::try_new_dispute(client, tx, amount)?;
+                self.process_special_transaction(kind, transaction)
+            }
+            Operation::Resolve { client, tx, amount } => {
+                let transaction = SpecialTransaction::try_new_resolve(client, tx, amount)?;
+                self.process_special_transaction(kind, transaction)
+            }
+            Operation::Chargeback { client, tx, amount } => {
+                let transaction = SpecialTransaction::try_new_chargeback(client, tx, amount)?;
+                self.process_special_transaction(kind, transaction)
+            }
+            Operation::Unlock { client } => {
+                retrieve_account(&mut self.storage, client)?.try_unlock()?;
+                Ok(None)
+            }
+            Operation::Freeze { client } => {
+                retrieve_account(&mut self.storage, client)?.try_freeze()?;
+                Ok(None)
+            }
+            Operation::Unfreeze { client } => {
+                retrieve_account(&mut self.storage, client)?.try_unfreeze()?;
+                Ok(None)
+            }
+            Operation::Close { client } => {
+                retrieve_account(&mut self.storage, client)?.try_close()?;
+                Ok(None)
+            }
+            Operation::SetCreditLimit {
+                client,
+                currency,
+                limit,
+            } => {
+                let account = retrieve_account(&mut self.storage, client)?;
+                account.try_set_credit_limit(currency, limit)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Removes all transactions whose dispute window has expired from the cache.
+    ///
+    /// Returns an `Error` if the storage fails. In that case, the transaction that couldn't be
+    /// removed has still expired, so it's only left in the cache like a pending transaction.
+    fn expire_transactions(&mut self) -> Result<()> {
+        let window = match self.dispute_window {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        while let Some(tx) = self.expiry.pop_expired(window) {
+            // A disputed transaction is only removed once it's resolved or charged back,
+            // and an authorized transaction once it's captured or voided.
+            if let Some(transaction) = self.storage.get_transaction(tx).map_err(storage_failure)? {
+                if transaction.is_pending() {
+                    continue;
+                }
+            }
+            self.storage
+                .remove_transaction(tx)
+                .map_err(storage_failure)?;
+        }
+        Ok(())
+    }
+
+    fn process_regular_transaction(
+        &mut self,
+        kind: OperationKind,
+        tx: Tx,
+        transaction: Transaction,
+    ) -> Result<Option<Movement>> {
         // Return an error if the transaction ID has been already processed successfully.
-        if self.transactions.contains_key(&tx) {
+        let cached = self.storage.get_transaction(tx).map_err(storage_failure)?;
+        if cached.is_some() || self.expiry.is_expired(tx) {
             return Err(TransactionIdAlreadyProcessed);
         }
 
-        // Return an error if the transaction is a withdrawal and the client account doesn't exist.
-        if let TransactionKind::Withdrawal = transaction.kind {
-            if !self.clients.contains_key(&transaction.client) {
+        // Return an error if the transaction is a withdrawal, a transfer, or an authorization
+        // and the client account doesn't exist.
+        if let TransactionKind::Withdrawal
+        | TransactionKind::Transfer
+        | TransactionKind::Authorization = transaction.kind
+        {
+            if self.storage.get_client(transaction.client).is_none() {
                 return Err(InvalidFirstTransaction);
             }
         }
 
-        // Retrieve or create the client account. Return an error if account is locked.
-        let account = retrieve_or_create_account(&mut self.clients, transaction.client)?;
+        // Compute the fee of the transaction (if any).
+        let (client, currency) = (transaction.client, transaction.currency);
+        let fee = self.fees.compute(kind, currency, transaction.amount)?;
+
+        // Retrieve or create the client account. Return an error if account isn't active.
+        // An account created for this transaction (i.e. by a deposit) is removed if the
+        // transaction fails, since a failed transaction *must* not modify any account.
+        let created = self.storage.get_client(client).is_none();
+        retrieve_or_create_account(&mut self.storage, client)?;
 
-        // Attempt to perform the deposit or withdrawal.
-        match transaction.kind {
-            TransactionKind::Deposit => account.try_deposit(transaction.amount)?,
-            TransactionKind::Withdrawal => account.try_withdrawal(transaction.amount)?,
+        // Attempt to charge the fee first, so that a withdrawal, a transfer, or an
+        // authorization can only use the funds left after the fee.
+        let limited = transaction.kind != TransactionKind::Deposit;
+        let charged = self
+            .fees
+            .try_charge(&mut self.storage, client, currency, fee, limited);
+        let charge = match charged {
+            Ok(charge) => charge,
+            Err(error) => {
+                if created {
+                    self.storage.remove_client(client);
+                }
+                return Err(error);
+            }
+        };
+
+        // Attempt to perform the deposit, withdrawal, transfer, or authorization on a copy of
+        // the client account and, for a transfer, of the recipient's account. Then, attempt to
+        // cache the transaction (only if it was successful, otherwise it could be disputed).
+        // The accounts are only written back once both have succeeded. On failure, refund the fee.
+        let mut account = retrieve_account(&mut self.storage, client)?.clone();
+        let mut recipient = None;
+        let result = match transaction.kind {
+            TransactionKind::Deposit => account.try_deposit(currency, transaction.amount),
+            TransactionKind::Withdrawal => account.try_withdrawal(currency, transaction.amount),
+            TransactionKind::Transfer => try_transfer(&self.storage, &mut account, &transaction)
+                .map(|received| recipient = Some(received)),
+            TransactionKind::Authorization => account.try_authorize(currency, transaction.amount),
+        }
+        .and_then(|_| {
+            self.storage
+                .insert_transaction(tx, transaction)
+                .map_err(storage_failure)
+        });
+        if let Err(error) = result {
+            self.fees.refund(&mut self.storage, charge);
+            if created {
+                self.storage.remove_client(client);
+            }
+            return Err(error);
+        }
+        self.storage.insert_client(client, account);
+        if let Some((id, recipient)) = recipient {
+            self.storage.insert_client(id, recipient);
         }
 
-        // Cache the transaction only if it was successful. Otherwise, it could be disputed.
-        self.transactions.insert(tx, transaction);
+        // Track the transaction until it expires if there's a dispute window.
+        if self.dispute_window.is_some() {
+            self.expiry.push(tx);
+        }
 
         // Return successfully.
-        Ok(())
+        Ok(Some(Movement {
+            client,
+            currency,
+            amount: transaction.amount,
+            fee,
+        }))
     }
 
-    fn process_special_transaction(&mut self, transaction: SpecialTransaction) -> Result<()> {
-        // Return an error if the transaction ID hasn't been already processed successfully.
-        let old_transaction = self
-            .transactions
-            .get_mut(&transaction.tx)
-            .ok_or(UnknownTransactionId)?;
+    fn process_special_transaction(
+        &mut self,
+        kind: OperationKind,
+        transaction: SpecialTransaction,
+    ) -> Result<Option<Movement>> {
+        // Return an error if the transaction ID hasn't been already processed successfully,
+        // or if it has expired. An expired transaction can still be referenced if it was
+        // already disputed when it expired, but it can't be disputed again.
+        let (mut old_transaction, expired) =
+            retrieve_transaction(&self.storage, &self.expiry, transaction.tx)?;
+        if expired && matches!(transaction.kind, SpecialTransactionKind::Dispute) {
+            return Err(DisputeWindowExpired);
+        }
+
+        // Determine the client account to apply this transaction to. If this transaction's
+        // client ID doesn't match the old one, this depends on the cross-client dispute policy.
+        let client = match self.cross_client_disputes {
+            _ if transaction.client == old_transaction.client => transaction.client,
+            CrossClientDisputePolicy::Reject => return Err(WrongClientId),
+            CrossClientDisputePolicy::ChargeDisputingClient => transaction.client,
+            CrossClientDisputePolicy::ChargeOriginalClient => old_transaction.client,
+        };
+
+        // Return an error if the transaction is fully reversed, or if there's nothing left
+        // to dispute, resolve, or charge back. Otherwise, determine the amount to target.
+        // A reversed transaction can't be referenced again, even after its account was unlocked.
+        // An authorization can only be referenced once it's captured.
+        match old_transaction.state {
+            TransactionState::Accepted | TransactionState::Disputed => {}
+            TransactionState::Reversed => return Err(TransactionAlreadyReversed),
+            TransactionState::Authorized => return Err(AuthorizationNotCaptured),
+            TransactionState::Voided => return Err(AuthorizationAlreadyVoided),
+        }
+        let amount = match transaction.kind {
+            SpecialTransactionKind::Dispute => match old_transaction.get_undisputed_amount() {
+                0 => return Err(TransactionAlreadyDisputed),
+                undisputed => transaction.try_get_amount(undisputed)?,
+            },
+            _ => match old_transaction.held {
+                0 => return Err(TransactionNotDisputed),
+                held => transaction.try_get_amount(held)?,
+            },
+        };
+        let currency = old_transaction.currency;
+        let fee = self.fees.compute(kind, currency, amount)?;
+
+        // Retrieve or create the client account. Return an error if account isn't active.
+        // An account created for this transaction (i.e. for a disputing client) is removed
+        // if the transaction fails, since a failed transaction *must* not modify any account.
+        let created = self.storage.get_client(client).is_none();
+        retrieve_or_create_account(&mut self.storage, client)?;
+
+        // Attempt to charge the fee of the transaction (if any) first.
+        let charged = self
+            .fees
+            .try_charge(&mut self.storage, client, currency, fee, false);
+        let charge = match charged {
+            Ok(charge) => charge,
+            Err(error) => {
+                if created {
+                    self.storage.remove_client(client);
+                }
+                return Err(error);
+            }
+        };
+
+        // Retrieve a copy of the client account and, for a transfer, of the recipient's account
+        // (unless the client is the recipient). They're only written back once both sides have
+        // succeeded, so that both accounts are updated atomically. The recipient's account
+        // exists, since it was created by the transfer.
+        let mut account = retrieve_account(&mut self.storage, client)?.clone();
+        let mut recipient = old_transaction
+            .recipient
+            .filter(|&recipient| recipient != client)
+            .map(|recipient| {
+                (
+                    recipient,
+                    self.storage
+                        .get_client(recipient)
+                        .expect("recipient's account doesn't exist")
+                        .clone(),
+                )
+            });
+
+        // Attempt to perform the dispute, resolve, or chargeback. On failure, refund the fee.
+        // For a transfer, the funds are held, released, or reversed in the recipient's account.
+        // For a withdrawal, or a deposit that exceeds the available balance, this depends on
+        // the dispute policies.
+        let (policy, balance_policy) = (self.withdrawal_disputes, self.negative_balance_disputes);
+        let result = match (&mut recipient, old_transaction.recipient) {
+            (Some((_, recipient)), _) => recipient.try_update_received(
+                &transaction.kind,
+                &mut old_transaction,
+                amount,
+                balance_policy,
+            ),
+            (None, Some(_)) => account.try_update_received(
+                &transaction.kind,
+                &mut old_transaction,
+                amount,
+                balance_policy,
+            ),
+            (None, None) => Ok(()),
+        }
+        .and_then(|_| match transaction.kind {
+            SpecialTransactionKind::Dispute => {
+                account.try_dispute(&mut old_transaction, amount, policy, balance_policy)
+            }
+            SpecialTransactionKind::Resolve => account.try_resolve(&mut old_transaction, amount),
+            SpecialTransactionKind::Chargeback => {
+                account.try_chargeback(&mut old_transaction, amount)
+            }
+        })
+        .and_then(|_| {
+            // Write the updated transaction back to the cache, or remove it if it has expired
+            // while it was disputed and no part of it is disputed anymore.
+            if expired && !old_transaction.is_pending() {
+                self.storage.remove_transaction(transaction.tx)
+            } else {
+                self.storage
+                    .insert_transaction(transaction.tx, old_transaction)
+            }
+            .map_err(storage_failure)
+        });
+        if let Err(error) = result {
+            self.fees.refund(&mut self.storage, charge);
+            if created {
+                self.storage.remove_client(client);
+            }
+            return Err(error);
+        }
+
+        self.storage.insert_client(client, account);
+        if let Some((id, recipient)) = recipient {
+            self.storage.insert_client(id, recipient);
+        }
+
+        // Return successfully.
+        Ok(Some(Movement {
+            client,
+            currency,
+            amount,
+            fee,
+        }))
+    }
+
+    fn process_settlement(
+        &mut self,
+        kind: OperationKind,
+        settlement: Settlement,
+    ) -> Result<Option<Movement>> {
+        // Return an error if the transaction ID hasn't been already processed successfully,
+        // or if it has expired. An authorized transaction is never removed before it's settled.
+        let (mut old_transaction, expired) =
+            retrieve_transaction(&self.storage, &self.expiry, settlement.tx)?;
 
         // Return an error if this transaction's client ID doesn't match the old one.
-        if transaction.client != old_transaction.client {
+        if settlement.client != old_transaction.client {
             return Err(WrongClientId);
         }
 
-        // Retrieve or create the client account. Return an error if account is locked.
-        let account = retrieve_or_create_account(&mut self.clients, transaction.client)?;
+        // Return an error if the old transaction isn't an authorization.
+        if old_transaction.kind != TransactionKind::Authorization {
+            return Err(TransactionNotAnAuthorization);
+        }
 
-        // Attempt to perform the dispute, resolve, or chargeback.
-        match transaction.kind {
-            SpecialTransactionKind::Dispute => match old_transaction.state {
-                TransactionState::Accepted => account.try_dispute(old_transaction)?,
-                TransactionState::Disputed => return Err(TransactionAlreadyDisputed),
-                TransactionState::Reversed => unreachable!("account should be locked"),
-            },
-            SpecialTransactionKind::Resolve => match old_transaction.state {
-                TransactionState::Accepted => return Err(TransactionNotDisputed),
-                TransactionState::Disputed => account.try_resolve(old_transaction)?,
-                TransactionState::Reversed => unreachable!("account should be locked"),
-            },
-            SpecialTransactionKind::Chargeback => match old_transaction.state {
-                TransactionState::Accepted => return Err(TransactionNotDisputed),
-                TransactionState::Disputed => account.try_chargeback(old_transaction)?,
-                TransactionState::Reversed => unreachable!("account should be locked"),
-            },
+        // Retrieve or create the client account. Return an error if account isn't active.
+        retrieve_or_create_account(&mut self.storage, settlement.client)?;
+
+        // Return an error if the authorization was already captured or voided.
+        // Otherwise, determine the amount to capture or release.
+        match old_transaction.state {
+            TransactionState::Authorized => {}
+            TransactionState::Voided => return Err(AuthorizationAlreadyVoided),
+            _ => return Err(AuthorizationAlreadyCaptured),
         }
+        let amount = match settlement.kind {
+            SettlementKind::Capture => settlement.try_get_amount(old_transaction.amount)?,
+            SettlementKind::Void => old_transaction.amount,
+        };
+
+        // Attempt to charge the fee of the transaction (if any) first.
+        let (client, currency) = (settlement.client, old_transaction.currency);
+        let fee = self.fees.compute(kind, currency, amount)?;
+        let charge = self
+            .fees
+            .try_charge(&mut self.storage, client, currency, fee, false)?;
+
+        // Attempt to perform the capture or void on a copy of the client account. Then, attempt
+        // to write the updated transaction back to the cache, or to remove it if it has expired
+        // while it was authorized. The account is only written back once both have succeeded.
+        // On failure, refund the fee.
+        let mut account = retrieve_account(&mut self.storage, client)?.clone();
+        let result = match settlement.kind {
+            SettlementKind::Capture => account.try_capture(&mut old_transaction, amount),
+            SettlementKind::Void => account.try_void(&mut old_transaction),
+        }
+        .and_then(|_| {
+            if expired {
+                self.storage.remove_transaction(settlement.tx)
+            } else {
+                self.storage
+                    .insert_transaction(settlement.tx, old_transaction)
+            }
+            .map_err(storage_failure)
+        });
+        if let Err(error) = result {
+            self.fees.refund(&mut self.storage, charge);
+            return Err(error);
+        }
+        self.storage.insert_client(client, account);
 
         // Return successfully.
+        Ok(Some(Movement {
+            client,
+            currency,
+            amount,
+            fee,
+        }))
+    }
+}
+
+/// The original state of the client accounts and transactions modified by a batch (or by a
+/// dry run), which is restored if any of its operations fails.
+struct Undo {
+    clients: HashMap<ClientId, Option<Client>>,
+    transactions: HashMap<Tx, Option<Transaction>>,
+    expiry: u64,
+}
+
+impl Undo {
+    fn new(expiry: u64) -> Self {
+        Self {
+            clients: HashMap::new(),
+            transactions: HashMap::new(),
+            expiry,
+        }
+    }
+
+    /// Records the original state of the client accounts and transaction that an operation
+    /// may modify, unless they were already recorded for a previous operation of the batch.
+    ///
+    /// These are the client account of the operation, the client account and recipient's
+    /// account of the transaction it references (or of a transfer), the fee account,
+    /// and the transaction it references.
+    ///
+    /// Returns an `Error` if the storage fails.
+    fn record<S: Storage>(
+        &mut self,
+        engine: &TransactionEngine<S>,
+        operation: Operation,
+    ) -> Result<()> {
+        let mut clients = vec![operation.client()];
+        if let Operation::Transfer { to, .. } = operation {
+            clients.push(to);
+        }
+        if let Some(tx) = operation.tx() {
+            let transaction = engine
+                .storage
+                .get_transaction(tx)
+                .map_err(storage_failure)?;
+            clients.extend(transaction.map(|t| t.client));
+            clients.extend(transaction.and_then(|t| t.recipient));
+            self.transactions.entry(tx).or_insert(transaction);
+        }
+        clients.extend(engine.fees.account);
+        for client in clients {
+            self.clients
+                .entry(client)
+                .or_insert_with(|| engine.storage.get_client(client).cloned());
+        }
         Ok(())
     }
+
+    /// Restores the original state of everything that was recorded.
+    ///
+    /// Returns an `Error` if the storage fails to restore a transaction, after restoring
+    /// everything else.
+    fn rollback<S: Storage>(self, engine: &mut TransactionEngine<S>) -> Result<()> {
+        for (client, account) in self.clients {
+            match account {
+                Some(account) => engine.storage.insert_client(client, account),
+                None => engine.storage.remove_client(client),
+            }
+        }
+        let mut result = Ok(());
+        for (tx, transaction) in self.transactions {
+            let restored = match transaction {
+                Some(transaction) => engine.storage.insert_transaction(tx, transaction),
+                None => engine.storage.remove_transaction(tx),
+            };
+            result = result.and(restored.map_err(storage_failure));
+        }
+        engine.expiry.rollback(self.expiry);
+        result
+    }
+}
+
+/// Retrieves a copy of a cached transaction by ID, along with whether it has expired.
+///
+/// Returns an `Error` if the transaction ID hasn't been already processed successfully,
+/// or if it has expired and was removed from the cache.
+fn retrieve_transaction<S: Storage>(
+    storage: &S,
+    expiry: &Expiry,
+    tx: Tx,
+) -> Result<(Transaction, bool)> {
+    let expired = expiry.is_expired(tx);
+    match storage.get_transaction(tx).map_err(storage_failure)? {
+        Some(transaction) => Ok((transaction, expired)),
+        None if expired => Err(DisputeWindowExpired),
+        None => Err(UnknownTransactionId),
+    }
 }
 
 /// Retrieves a client account by ID, or creates a new account if it doesn't already exist.
 ///
-/// Returns an `Error` if the client account is locked.
-fn retrieve_or_create_account(clients: &mut ClientMap, client: ClientId) -> Result<&mut Client> {
-    let account = clients.entry(client).or_insert_with(Client::init);
-    if account.is_locked() {
-        return Err(ClientAccountLocked);
+/// Returns an `Error` if the client account isn't active (i.e. locked, frozen, or closed).
+fn retrieve_or_create_account<S: Storage>(
+    storage: &mut S,
+    client: ClientId,
+) -> Result<&mut Client> {
+    if storage.get_client(client).is_none() {
+        storage.insert_client(client, Client::init());
     }
+    // The client account was just inserted if it didn't exist.
+    let account = storage
+        .get_client_mut(client)
+        .expect("client account wasn't inserted");
+    account.check_active()?;
     Ok(account)
 }
+
+/// Attempts to debit a transfer from a copy of the sender's account, and to credit it to a copy
+/// of the recipient's account (which is created if it doesn't already exist).
+///
+/// Returns the recipient's client ID and updated account, to write back along with the sender's.
+fn try_transfer<S: Storage>(
+    storage: &S,
+    account: &mut Client,
+    transaction: &Transaction,
+) -> Result<(ClientId, Client)> {
+    let (currency, amount) = (transaction.currency, transaction.amount);
+    let recipient = transaction.recipient.expect("transfer has no recipient");
+
+    // Return an error if the recipient's account isn't active, before modifying any account.
+    let mut received = match storage.get_client(recipient) {
+        Some(received) => {
+            received.check_active()?;
+            received.clone()
+        }
+        None => Client::init(),
+    };
+
+    // Attempt to debit the sender's account and to credit the recipient's account.
+    account.try_withdrawal(currency, amount)?;
+    received.try_deposit(currency, amount)?;
+    Ok((recipient, received))
+}
+
+/// Converts an error of the storage into the error of the operation that accessed it.
+fn storage_failure(_: Error) -> TransactionError {
+    StorageFailure
+}
+
+/// Retrieves an existing client account by ID, regardless of its status.
+///
+/// Returns an `Error` if the client account doesn't exist.
+fn retrieve_account<S: Storage>(storage: &mut S, client: ClientId) -> Result<&mut Client> {
+    storage.get_client_mut(client).ok_or(UnknownClientId)
+}
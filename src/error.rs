@@ -1,3 +1,4 @@
+use crate::Operation;
 use thiserror::Error;
 
 /******************************************
@@ -8,26 +9,92 @@ use thiserror::Error;
 ///
 /// When that happens, the transaction engine must guarantee that
 /// the client account referenced by this transaction was NOT modified.
-#[derive(Debug, Error)]
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
 pub enum TransactionError {
+    #[error("authorization already captured")]
+    AuthorizationAlreadyCaptured,
+    #[error("authorization already voided")]
+    AuthorizationAlreadyVoided,
+    #[error("authorization not captured")]
+    AuthorizationNotCaptured,
     #[error("balance would overflow")]
     BalanceWouldOverflow,
+    #[error("client account already frozen")]
+    ClientAccountAlreadyFrozen,
+    #[error("client account closed")]
+    ClientAccountClosed,
+    #[error("client account frozen")]
+    ClientAccountFrozen,
     #[error("client account locked")]
     ClientAccountLocked,
+    #[error("client account not empty")]
+    ClientAccountNotEmpty,
+    #[error("client account not frozen")]
+    ClientAccountNotFrozen,
+    #[error("client account not locked")]
+    ClientAccountNotLocked,
+    #[error("dispute exceeds available funds")]
+    DisputeExceedsAvailableFunds,
+    #[error("dispute window expired")]
+    DisputeWindowExpired,
     #[error("insufficient available funds")]
     InsufficientAvailableFunds,
+    #[error("invalid capture amount")]
+    InvalidCaptureAmount,
+    #[error("invalid dispute amount")]
+    InvalidDisputeAmount,
     #[error("invalid first transaction")]
     InvalidFirstTransaction,
+    #[error("overdraft limit exceeded")]
+    OverdraftLimitExceeded,
+    #[error("storage failure")]
+    StorageFailure,
+    #[error("total balance below floor")]
+    TotalBalanceBelowFloor,
     #[error("transaction already disputed")]
     TransactionAlreadyDisputed,
+    #[error("transaction already reversed")]
+    TransactionAlreadyReversed,
     #[error("transaction amount too large")]
     TransactionAmountTooLarge,
     #[error("transaction id already processed")]
     TransactionIdAlreadyProcessed,
+    #[error("transaction not an authorization")]
+    TransactionNotAnAuthorization,
     #[error("transaction not disputed")]
     TransactionNotDisputed,
+    #[error("transfer to same client")]
+    TransferToSameClient,
+    #[error("unknown client id")]
+    UnknownClientId,
     #[error("unknown transaction id")]
     UnknownTransactionId,
+    #[error("withdrawal not disputable")]
+    WithdrawalNotDisputable,
     #[error("wrong client id")]
     WrongClientId,
 }
+
+/// An error returned when a batch of operations is rolled back because one of them failed.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+#[error("operation #{index} of batch failed: {error}")]
+pub struct BatchError {
+    /// The index of the failed operation in the batch.
+    pub index: usize,
+    /// The failed operation.
+    pub operation: Operation,
+    /// The reason why the operation failed.
+    pub error: TransactionError,
+}
+
+/// An error returned by `EngineHandle::submit`.
+#[cfg(feature = "actor")]
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum SubmitError {
+    /// The engine processed the operation, but rejected it.
+    #[error(transparent)]
+    Transaction(#[from] TransactionError),
+    /// The engine was shut down before it could process the operation.
+    #[error("transaction engine shut down")]
+    ShutDown,
+}
@@ -0,0 +1,115 @@
+use crate::{AccountStatus, Amount, Balance, ClientId, CreditLimit, Currency, OperationKind};
+use crate::{TransactionError, TransactionId};
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// An event emitted by the transaction engine for every processed transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A deposit was accepted and credited to the client account.
+    DepositAccepted(Receipt),
+    /// A withdrawal was accepted and debited from the client account.
+    WithdrawalAccepted(Receipt),
+    /// A transfer was accepted, debited from the client account and credited to the recipient's
+    /// account. The receipt reports the balances of the sending client account.
+    TransferAccepted(Receipt),
+    /// An authorization was accepted. Its amount is now held in the client account.
+    FundsAuthorized(Receipt),
+    /// A capture was accepted. The captured amount was debited from the client account,
+    /// and the rest of the authorized amount (if any) was released.
+    AuthorizationCaptured(Receipt),
+    /// A void was accepted. The authorized amount was released in the client account.
+    AuthorizationVoided(Receipt),
+    /// A dispute was accepted. For a deposit, its amount is now held in the client account.
+    /// For a withdrawal, this depends on the `WithdrawalDisputePolicy`.
+    FundsHeld(Receipt),
+    /// A resolve was accepted. The funds held by the dispute (if any) were released.
+    DisputeResolved(Receipt),
+    /// A chargeback was accepted. The transaction was (partly) reversed and the client account
+    /// locked.
+    AccountLocked(Receipt),
+    /// The status of a client account was changed by an admin.
+    StatusChanged(StatusChange),
+    /// The credit limit of a client account was changed by an admin.
+    CreditLimitChanged(CreditLimitChange),
+    /// A transaction was rejected. The client account was NOT modified.
+    Rejected(Rejection),
+}
+
+impl Event {
+    /// Returns whether the transaction was accepted.
+    pub fn is_accepted(&self) -> bool {
+        !matches!(self, Event::Rejected(_))
+    }
+
+    /// Returns the reason why the transaction was rejected, if it was.
+    pub fn get_error(&self) -> Option<TransactionError> {
+        match self {
+            Event::Rejected(rejection) => Some(rejection.error),
+            _ => None,
+        }
+    }
+}
+
+/// The details of an accepted transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Receipt {
+    /// The client ID of the client account that the transaction was applied to
+    /// (see `CrossClientDisputePolicy`).
+    pub client: ClientId,
+    /// The transaction ID of the transaction (or of the transaction it references).
+    pub tx: TransactionId,
+    /// The amount of the transaction (or the part of the amount of the transaction it
+    /// references that was captured, voided, disputed, resolved, or charged back).
+    pub amount: Amount,
+    /// The fee charged to the client account for the transaction (see `FeePolicy`),
+    /// which is already deducted from the balances below.
+    pub fee: Amount,
+    /// The currency of the transaction (or of the transaction it references).
+    pub currency: Currency,
+    /// The available balance in that currency of the client account after the transaction.
+    pub available: Balance,
+    /// The held balance in that currency of the client account after the transaction.
+    pub held: Balance,
+    /// The total balance in that currency of the client account after the transaction.
+    pub total: Balance,
+}
+
+/// The details of a client account whose status was changed by an admin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StatusChange {
+    /// The client ID of the client account.
+    pub client: ClientId,
+    /// The new status of the client account.
+    pub status: AccountStatus,
+}
+
+/// The details of a client account whose credit limit was changed by an admin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CreditLimitChange {
+    /// The client ID of the client account.
+    pub client: ClientId,
+    /// The currency of the credit limit.
+    pub currency: Currency,
+    /// The new credit limit of the client account in that currency.
+    pub limit: CreditLimit,
+}
+
+/// The details of a rejected transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    /// The kind of the transaction.
+    pub kind: OperationKind,
+    /// The client ID of the transaction.
+    pub client: ClientId,
+    /// The transaction ID of the transaction (or of the transaction it references), if any.
+    pub tx: Option<TransactionId>,
+    /// The amount of the transaction, if it specifies one.
+    pub amount: Option<Amount>,
+    /// The currency of the transaction, if it specifies one.
+    pub currency: Option<Currency>,
+    /// The reason why the transaction was rejected.
+    pub error: TransactionError,
+}
@@ -0,0 +1,146 @@
+use crate::TransactionError;
+use crate::{Amount, Balance, Client, ClientId, Currency, OperationKind, Result, Storage};
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// A policy that determines the fee of each operation processed by the transaction engine.
+///
+/// It's implemented by `StandardFeePolicy`, and by any closure with the same signature as
+/// the `fee` method (e.g. to charge different fees per currency).
+pub trait FeePolicy: Send {
+    /// Returns the fee of an operation of the given kind, currency, and amount (in the smallest
+    /// unit of the currency), or 0 if it's free.
+    ///
+    /// For a capture, dispute, resolve, or chargeback, the amount is the part of the referenced
+    /// transaction's amount it targets. For a void, it's the released amount.
+    fn fee(&self, kind: OperationKind, currency: Currency, amount: Amount) -> Amount;
+}
+
+impl<F: Fn(OperationKind, Currency, Amount) -> Amount + Send> FeePolicy for F {
+    fn fee(&self, kind: OperationKind, currency: Currency, amount: Amount) -> Amount {
+        self(kind, currency, amount)
+    }
+}
+
+/// A fee policy that charges a fixed fee plus a percentage of the amount on withdrawals,
+/// and a flat fee on chargebacks. All other operations are free.
+///
+/// The fees are expressed in the smallest unit of the currency of the operation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StandardFeePolicy {
+    /// The fixed fee of a withdrawal.
+    pub withdrawal_fixed: Amount,
+    /// The percentage of the amount of a withdrawal charged on top of the fixed fee,
+    /// in basis points (i.e. hundredths of a percent). The result is rounded down.
+    pub withdrawal_rate: u32,
+    /// The flat fee of a chargeback.
+    pub chargeback_flat: Amount,
+}
+
+impl FeePolicy for StandardFeePolicy {
+    fn fee(&self, kind: OperationKind, _: Currency, amount: Amount) -> Amount {
+        match kind {
+            OperationKind::Withdrawal => {
+                // This computation is performed with `u128` so that it can't overflow.
+                let rate = u128::from(amount) * u128::from(self.withdrawal_rate) / 10_000;
+                let rate = Amount::try_from(rate).unwrap_or(Amount::MAX);
+                self.withdrawal_fixed.saturating_add(rate)
+            }
+            OperationKind::Chargeback => self.chargeback_flat,
+            _ => 0,
+        }
+    }
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+/// The fee policy of the transaction engine and the account its fees are credited to.
+#[derive(Default)]
+pub(crate) struct Fees {
+    pub(crate) policy: Option<Box<dyn FeePolicy>>,
+    pub(crate) account: Option<ClientId>,
+}
+
+impl Fees {
+    /// Computes the fee of an operation of the given kind, currency, and amount.
+    ///
+    /// Returns an `Error` if the fee doesn't fit in a `Balance`.
+    pub(crate) fn compute(
+        &self,
+        kind: OperationKind,
+        currency: Currency,
+        amount: Balance,
+    ) -> Result<Balance> {
+        let policy = match &self.policy {
+            Some(policy) => policy,
+            None => return Ok(0),
+        };
+        // The amount of a transaction is always converted from an `Amount`,
+        // so it's guaranteed to be non-negative.
+        let fee = policy.fee(kind, currency, amount as Amount);
+        Balance::try_from(fee).map_err(|_| TransactionError::TransactionAmountTooLarge)
+    }
+
+    /// Attempts to charge a fee to a client account and to credit it to the fee account
+    /// (if any), which is created if it doesn't already exist.
+    ///
+    /// Returns the `Charge` to pass to `refund` if the transaction it was charged for fails,
+    /// or an `Error` if the fee can't be charged or credited. In that case, no account
+    /// is modified (nor created).
+    pub(crate) fn try_charge<S: Storage>(
+        &self,
+        storage: &mut S,
+        client: ClientId,
+        currency: Currency,
+        fee: Balance,
+        limited: bool,
+    ) -> Result<Charge> {
+        let mut charge = Charge::default();
+        if fee == 0 {
+            return Ok(charge);
+        }
+
+        // Attempt to debit the client account.
+        let account = storage
+            .get_client_mut(client)
+            .ok_or(TransactionError::UnknownClientId)?;
+        charge.accounts.push((client, Some(account.clone())));
+        account.try_charge_fee(currency, fee, limited)?;
+
+        // Attempt to credit the fee account. On failure, restore the client account.
+        if let Some(id) = self.account {
+            charge.accounts.push((id, storage.get_client(id).cloned()));
+            let mut account = storage.get_client(id).cloned().unwrap_or_else(Client::init);
+            if let Err(error) = account.try_collect_fee(currency, fee) {
+                self.refund(storage, charge);
+                return Err(error);
+            }
+            storage.insert_client(id, account);
+        }
+        Ok(charge)
+    }
+
+    /// Refunds a fee that was just charged to a client account by `try_charge`,
+    /// because the transaction it was charged for failed.
+    ///
+    /// The accounts it modified are restored as they were before the charge, so that
+    /// the fee account (or its wallet) is removed if the charge created it.
+    pub(crate) fn refund<S: Storage>(&self, storage: &mut S, charge: Charge) {
+        for (id, account) in charge.accounts.into_iter().rev() {
+            match account {
+                Some(account) => storage.insert_client(id, account),
+                None => storage.remove_client(id),
+            }
+        }
+    }
+}
+
+/// The accounts modified by a fee charge, as they were before it.
+#[derive(Default)]
+pub(crate) struct Charge {
+    accounts: Vec<(ClientId, Option<Client>)>,
+}
@@ -0,0 +1,106 @@
+use crate::Operation;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+/// An append-only journal of accepted operations.
+///
+/// Each entry is serialized as a single line of JSON (i.e. JSON Lines format).
+/// Since a line is only complete once its trailing newline has been written, a crash
+/// in the middle of an append leaves at most one incomplete line at the end of the file.
+pub(crate) struct Journal {
+    file: File,
+    length: u64,
+}
+
+/// A single entry of the journal.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+pub(crate) enum Entry {
+    /// A single operation, serialized as a JSON object.
+    Operation(Operation),
+    /// A batch of operations that were accepted atomically, serialized as a JSON array,
+    /// so that a crash can't leave only part of the batch in the journal.
+    Batch(Vec<Operation>),
+}
+
+impl Journal {
+    /// Opens the journal at the given path in append mode, creating it if it doesn't exist.
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let length = file.metadata()?.len();
+        Ok(Self { file, length })
+    }
+
+    /// Returns the length of the journal in bytes, which is also the offset
+    /// at which the next operation will be appended.
+    pub(crate) fn len(&self) -> u64 {
+        self.length
+    }
+
+    /// Reads all complete entries from the journal at the given path, in order,
+    /// starting at the given byte offset, and passes them one by one to the `replay` closure.
+    ///
+    /// If the last line is incomplete (i.e. the process crashed in the middle of an append),
+    /// it is truncated from the file. Does nothing if the file doesn't exist and the offset
+    /// is zero. Returns an `Error` if the journal is shorter than the offset.
+    pub(crate) fn replay<P, F>(path: P, offset: u64, mut replay: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(Entry) -> Result<()>,
+    {
+        let file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound && offset == 0 => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        if file.metadata()?.len() < offset {
+            let message = format!("journal is shorter than offset {}", offset);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+
+        let mut reader = BufReader::new(&file);
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut line = Vec::new();
+        let mut length = offset;
+        loop {
+            line.clear();
+            let n = reader.read_until(b'\n', &mut line)?;
+            if n == 0 {
+                break;
+            }
+            if line.last() != Some(&b'\n') {
+                // Incomplete last line: it was never acknowledged, so it's safe to discard it.
+                file.set_len(length)?;
+                break;
+            }
+            let entry =
+                serde_json::from_slice(&line).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            replay(entry)?;
+            length += n as u64;
+        }
+        Ok(())
+    }
+
+    /// Appends a single entry to the journal.
+    ///
+    /// The whole line is handed to the operating system in a single write, and then synced
+    /// to the storage device before this method returns (i.e. once per operation or batch),
+    /// which means that the entry survives a crash of the process or of the machine as soon
+    /// as its operation is acknowledged. This trades throughput for durability: each append
+    /// waits for the device.
+    pub(crate) fn append(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        self.length += line.len() as u64;
+        Ok(())
+    }
+}
@@ -35,34 +35,69 @@
 //! assert!(!client2.is_locked());
 //! ```
 
+#[cfg(feature = "actor")]
+mod actor;
 mod client;
+mod concurrent;
+mod currency;
+mod disk;
 mod engine;
 mod error;
+mod event;
+mod fee;
+mod journal;
+mod operation;
+mod policy;
+mod precision;
+mod shard;
+mod snapshot;
+mod storage;
 mod transaction;
+mod window;
 
 /******************************************
  *               PUBLIC API               *
  ******************************************/
 
-pub use client::{Client, ClientId};
+#[cfg(feature = "actor")]
+pub use actor::{EngineActor, EngineHandle};
+pub use client::{AccountStatus, Client, ClientId, CreditLimit, Wallet};
+pub use concurrent::ConcurrentEngine;
+pub use currency::{Currency, ParseCurrencyError};
+pub use disk::DiskStorage;
 pub use engine::TransactionEngine;
-pub use error::TransactionError;
-pub use transaction::TransactionId;
+#[cfg(feature = "actor")]
+pub use error::SubmitError;
+pub use error::{BatchError, TransactionError};
+pub use event::{CreditLimitChange, Event, Receipt, Rejection, StatusChange};
+pub use fee::{FeePolicy, StandardFeePolicy};
+pub use operation::{Operation, OperationKind};
+pub use policy::{CrossClientDisputePolicy, NegativeBalanceDisputePolicy, WithdrawalDisputePolicy};
+pub use precision::{ParseAmountError, DEFAULT_PRECISION, MAX_PRECISION};
+pub use shard::ShardedEngine;
+pub use storage::{HashMapStorage, Storage, TransactionIter, VecStorage};
+pub use transaction::{Transaction, TransactionId, TransactionKind};
+pub use transaction::{TransactionState, TransactionView};
+pub use window::DisputeWindow;
 
-/// An amount in the smallest unit of currency.
+/// An amount in the smallest unit of a currency.
 pub type Amount = u64;
 
-/// An account balance in the smallest unit of currency.
+/// An account balance in the smallest unit of a currency.
 ///
 /// This is an `i64` instead of a `u64` because an account balance can become negative
-/// if a deposit is successfully disputed after being withdrawn.
+/// if a deposit is successfully disputed after being withdrawn, or if a withdrawal uses
+/// the overdraft of a client account (see `CreditLimit`).
 pub type Balance = i64;
 
 /*******************************************
  *               PRIVATE API               *
  *******************************************/
 
+pub(crate) use fee::Fees;
+pub(crate) use journal::{Entry, Journal};
 pub(crate) use transaction::*;
+pub(crate) use window::Expiry;
 
 /// A transaction result.
 pub(crate) type Result<T> = std::result::Result<T, TransactionError>;
@@ -1,7 +1,11 @@
-use csv::{ReaderBuilder, Trim, Writer};
-use rust_decimal::prelude::*;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use transaction_engine::{ClientId, TransactionEngine, TransactionId};
+use csv::{ByteRecord, ReaderBuilder, Terminator, Trim, Writer};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use transaction_engine::{ClientId, Currency, Operation, TransactionEngine, TransactionId};
+use transaction_engine::{CrossClientDisputePolicy, TransactionError, MAX_PRECISION};
 
 /*****************************************
  *               CSV INPUT               *
@@ -12,38 +16,27 @@ struct InputCsvRow {
     r#type: TransactionType,
     client: ClientId,
     tx: TransactionId,
-    amount: Option<Amount>,
+    amount: Option<String>,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    to: Option<ClientId>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Deserialize)]
 #[serde(rename_all = "lowercase")]
 enum TransactionType {
     Deposit,
     Withdrawal,
+    Transfer,
+    Authorize,
+    Capture,
+    Void,
     Dispute,
     Resolve,
     Chargeback,
 }
 
-struct Amount(u64);
-
-impl<'de> Deserialize<'de> for Amount {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        use serde::de::Error;
-        let string = String::deserialize(deserializer)?;
-        let decimal = Decimal::from_str(&string).map_err(Error::custom)?;
-        let decimal = match decimal.checked_mul(Decimal::new(10000, 0)) {
-            Some(amount) => amount,
-            None => return Err(Error::custom("invalid amount")),
-        };
-        let amount = match decimal.to_u64() {
-            Some(amount) => amount,
-            None => return Err(Error::custom("invalid amount")),
-        };
-        Ok(Amount(amount))
-    }
-}
-
 /******************************************
  *               CSV OUTPUT               *
  ******************************************/
@@ -51,101 +44,489 @@ impl<'de> Deserialize<'de> for Amount {
 #[derive(Serialize)]
 struct OutputCsvRow {
     client: ClientId,
-    available: Balance,
-    held: Balance,
-    total: Balance,
+    currency: Currency,
+    available: String,
+    held: String,
+    total: String,
     locked: bool,
 }
 
-struct Balance(i64);
+#[derive(Serialize)]
+struct VerdictCsvRow {
+    line: u64,
+    verdict: String,
+}
+
+/***************************************
+ *               REJECTS               *
+ ***************************************/
+
+#[derive(Clone, Copy)]
+enum RejectsFormat {
+    Csv,
+    Json,
+}
+
+/// A rejected row. In CSV, its operation (if it was parsed) is written as a JSON string.
+/// Its raw text is missing if the row couldn't even be read as a CSV record.
+#[derive(Serialize)]
+struct RejectRow<O> {
+    line: u64,
+    row: Option<String>,
+    operation: Option<O>,
+    error: String,
+}
+
+enum RejectsWriter {
+    Csv(Box<Writer<File>>),
+    Json(BufWriter<File>),
+}
+
+/// Writes every rejected row to a rejects file, along with its raw text.
+struct Rejects {
+    writer: RejectsWriter,
+    /// The input CSV file, from which the raw text of the rejected rows is read.
+    input: File,
+}
+
+impl Rejects {
+    fn create(path: &str, format: RejectsFormat, input_path: &str) -> std::io::Result<Self> {
+        let input = File::open(input_path)?;
+        let file = File::create(path)?;
+        let writer = match format {
+            RejectsFormat::Csv => RejectsWriter::Csv(Box::new(Writer::from_writer(file))),
+            RejectsFormat::Json => RejectsWriter::Json(BufWriter::new(file)),
+        };
+        Ok(Self { writer, input })
+    }
+
+    /// Writes a rejected row, whose raw text is found at the given byte range of the input
+    /// CSV file (if it was read). On failure, print an error message and exit the program.
+    fn write(
+        &mut self,
+        line: u64,
+        bytes: Option<Range<u64>>,
+        operation: Option<Operation>,
+        error: String,
+    ) {
+        let row = match bytes.map(|bytes| self.read_row(bytes)).transpose() {
+            Ok(row) => row,
+            Err(e) => {
+                eprintln!("error: failed to read row from input file: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let result = match &mut self.writer {
+            RejectsWriter::Csv(writer) => operation
+                .map(|o| serde_json::to_string(&o))
+                .transpose()
+                .map_err(std::io::Error::from)
+                .and_then(|operation| {
+                    let row = RejectRow {
+                        line,
+                        row,
+                        operation,
+                        error,
+                    };
+                    writer.serialize(row).map_err(std::io::Error::from)
+                }),
+            RejectsWriter::Json(writer) => {
+                let row = RejectRow {
+                    line,
+                    row,
+                    operation,
+                    error,
+                };
+                serde_json::to_writer(&mut *writer, &row)
+                    .map_err(std::io::Error::from)
+                    .and_then(|()| writer.write_all(b"\n"))
+            }
+        };
+        result.unwrap_or_else(|e| {
+            eprintln!("error: failed to write rejects file: {}", e);
+            std::process::exit(1);
+        });
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.writer {
+            RejectsWriter::Csv(writer) => writer.flush(),
+            RejectsWriter::Json(writer) => writer.flush(),
+        }
+    }
+
+    /// Reads the raw text of a row from the input CSV file, without its line terminator.
+    fn read_row(&mut self, bytes: Range<u64>) -> std::io::Result<String> {
+        let mut row = vec![0; (bytes.end - bytes.start) as usize];
+        self.input.seek(SeekFrom::Start(bytes.start))?;
+        self.input.read_exact(&mut row)?;
+        if row.last() == Some(&b'\n') {
+            row.pop();
+        }
+        if row.last() == Some(&b'\r') {
+            row.pop();
+        }
+        Ok(String::from_utf8_lossy(&row).into_owned())
+    }
+}
+
+/***************************************
+ *               SUMMARY               *
+ ***************************************/
+
+/// The number of rows of each outcome: accepted, unparsable, or rejected by the engine
+/// (per `TransactionError` variant).
+#[derive(Default)]
+struct Summary {
+    rows: u64,
+    accepted: u64,
+    unparsable: u64,
+    rejected: BTreeMap<String, u64>,
+}
+
+impl Summary {
+    fn record(&mut self, outcome: Result<(), Option<TransactionError>>) {
+        self.rows += 1;
+        match outcome {
+            Ok(()) => self.accepted += 1,
+            Err(None) => self.unparsable += 1,
+            Err(Some(e)) => *self.rejected.entry(format!("{:?}", e)).or_default() += 1,
+        }
+    }
+
+    /// Prints the summary to standard error, since standard output is reserved for the CSV
+    /// output.
+    fn print(&self) {
+        eprintln!("summary: {} rows", self.rows);
+        eprintln!("  accepted: {}", self.accepted);
+        eprintln!("  unparsable: {}", self.unparsable);
+        for (error, count) in &self.rejected {
+            eprintln!("  rejected ({}): {}", error, count);
+        }
+    }
+}
+
+/************************************
+ *               ARGS               *
+ ************************************/
 
-impl Serialize for Balance {
-    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let decimal = Decimal::new(self.0, 4);
-        serializer.serialize_str(&decimal.to_string())
+struct Args {
+    path: String,
+    precisions: Vec<(Currency, u32)>,
+    cross_client_disputes: CrossClientDisputePolicy,
+    dry_run: bool,
+    rejects: Option<String>,
+    rejects_format: RejectsFormat,
+}
+
+/// Parses the command line arguments: the input CSV file's path, followed by any of these
+/// options:
+///
+/// - `--precision <currency>=<decimal places>` (e.g. `--precision BTC=8`), which can be
+///   repeated. The default currency is written without a code (e.g. `--precision =2`).
+/// - `--cross-client-disputes <reject|disputing|original>` to reject a dispute whose client
+///   ID differs from the referenced transaction's (the default), or to charge it to the
+///   disputing client or to the original client.
+/// - `--dry-run` to print a verdict for every row instead of the final balances.
+/// - `--rejects <path>` to write every rejected row to a rejects file, with its line number,
+///   its raw text, its parsed operation (if any), and the `TransactionError` variant (or the
+///   parse error) that rejected it.
+/// - `--rejects-format <csv|json>` to write the rejects file as CSV (the default) or as JSON
+///   lines.
+///
+/// On failure, print an error message and exit the program.
+fn parse_args() -> Args {
+    let mut path = None;
+    let mut precisions = Vec::new();
+    let mut cross_client_disputes = CrossClientDisputePolicy::Reject;
+    let mut dry_run = false;
+    let mut rejects = None;
+    let mut rejects_format = RejectsFormat::Csv;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--precision" => {
+                let option = args.next().unwrap_or_else(|| exit_with_usage());
+                let precision = option.split_once('=').and_then(|(currency, places)| {
+                    let places = places.parse().ok().filter(|&p| p <= MAX_PRECISION)?;
+                    Some((currency.parse().ok()?, places))
+                });
+                precisions.push(precision.unwrap_or_else(|| {
+                    eprintln!("error: invalid precision {:?}", option);
+                    std::process::exit(1);
+                }));
+            }
+            "--cross-client-disputes" => {
+                let option = args.next().unwrap_or_else(|| exit_with_usage());
+                cross_client_disputes = match option.as_str() {
+                    "reject" => CrossClientDisputePolicy::Reject,
+                    "disputing" => CrossClientDisputePolicy::ChargeDisputingClient,
+                    "original" => CrossClientDisputePolicy::ChargeOriginalClient,
+                    _ => {
+                        eprintln!("error: invalid cross-client dispute policy {:?}", option);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--dry-run" => dry_run = true,
+            "--rejects" => rejects = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            "--rejects-format" => {
+                let option = args.next().unwrap_or_else(|| exit_with_usage());
+                rejects_format = match option.as_str() {
+                    "csv" => RejectsFormat::Csv,
+                    "json" => RejectsFormat::Json,
+                    _ => {
+                        eprintln!("error: invalid rejects format {:?}", option);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            _ => {
+                if path.replace(arg).is_some() {
+                    exit_with_usage();
+                }
+            }
+        }
+    }
+    Args {
+        path: path.unwrap_or_else(|| exit_with_usage()),
+        precisions,
+        cross_client_disputes,
+        dry_run,
+        rejects,
+        rejects_format,
     }
 }
 
+fn exit_with_usage() -> ! {
+    eprintln!("error: please provide path to input csv file as first argument");
+    eprintln!("usage: transaction_engine <input csv> [--precision <currency>=<places>]...");
+    eprintln!("       [--cross-client-disputes <reject|disputing|original>] [--dry-run]");
+    eprintln!("       [--rejects <path>] [--rejects-format <csv|json>]");
+    std::process::exit(1);
+}
+
+/*************************************
+ *               PARSE               *
+ *************************************/
+
+/// Converts an input row into an operation, using the transaction engine to parse its amount
+/// with the precision of its currency.
+///
+/// On failure, return the reason as a warning message.
+fn parse_operation(engine: &TransactionEngine, row: InputCsvRow) -> Result<Operation, String> {
+    let (client, tx) = (row.client, row.tx);
+
+    // Determine the currency of the transaction. A row without a currency uses the default
+    // one. A dispute, resolve, or chargeback uses the currency of the transaction it references.
+    let currency = match row.r#type {
+        TransactionType::Deposit
+        | TransactionType::Withdrawal
+        | TransactionType::Transfer
+        | TransactionType::Authorize => row.currency,
+        _ => match engine.get_transaction(tx) {
+            Ok(transaction) => transaction.map(|t| t.get_currency()),
+            Err(e) => return Err(format!("failed to read transaction: {}", e)),
+        },
+    };
+    let currency = currency.unwrap_or_default();
+
+    // Parse the amount with the precision of the currency.
+    let amount = match row.amount.map(|a| engine.parse_amount(currency, &a)) {
+        Some(Ok(amount)) => Some(amount),
+        Some(Err(e)) => return Err(format!("failed to parse amount: {}", e)),
+        None => None,
+    };
+
+    // Build the operation. A transfer must specify a recipient (`to`). A capture, dispute,
+    // resolve, or chargeback with an amount only targets part of the transaction it references.
+    let operation = match (row.r#type, amount) {
+        (TransactionType::Deposit, Some(amount)) => Operation::Deposit {
+            client,
+            tx,
+            amount,
+            currency,
+        },
+        (TransactionType::Withdrawal, Some(amount)) => Operation::Withdrawal {
+            client,
+            tx,
+            amount,
+            currency,
+        },
+        (TransactionType::Transfer, Some(amount)) => match row.to {
+            Some(to) => Operation::Transfer {
+                client,
+                tx,
+                to,
+                amount,
+                currency,
+            },
+            None => return Err(format!("{:?} must specify recipient", row.r#type)),
+        },
+        (TransactionType::Authorize, Some(amount)) => Operation::Authorize {
+            client,
+            tx,
+            amount,
+            currency,
+        },
+        (TransactionType::Capture, amount) => Operation::Capture { client, tx, amount },
+        (TransactionType::Void, None) => Operation::Void { client, tx },
+        (TransactionType::Void, Some(_)) => {
+            return Err(format!("{:?} cannot specify amount", row.r#type))
+        }
+        (TransactionType::Dispute, amount) => Operation::Dispute { client, tx, amount },
+        (TransactionType::Resolve, amount) => Operation::Resolve { client, tx, amount },
+        (TransactionType::Chargeback, amount) => Operation::Chargeback { client, tx, amount },
+        (_, None) => return Err(format!("{:?} must specify amount", row.r#type)),
+    };
+    Ok(operation)
+}
+
 /************************************
  *               MAIN               *
  ************************************/
 
 fn main() {
-    // Read the input CSV file's path from the first argument.
+    // Read the input CSV file's path and options from the arguments.
     // On failure, print an error message and exit the program.
-    let path = std::env::args().nth(1).unwrap_or_else(|| {
-        eprintln!("error: please provide path to input csv file as first argument");
-        std::process::exit(1);
-    });
+    let args = parse_args();
 
-    // Build a CSV file reader that trims leading and trailing whitespaces.
+    // Build a CSV file reader. Leading and trailing whitespaces are trimmed from every row.
+    // Rows are terminated by LF only, so that the line numbers of CRLF-terminated rows are
+    // reported correctly (the CR is trimmed as a trailing whitespace). A row with too few or
+    // too many fields is read anyway, so that it's rejected with its raw text.
     // On failure, print an error message and exit the program.
     let mut reader = ReaderBuilder::new()
         .trim(Trim::All)
-        .from_path(&path)
+        .flexible(true)
+        .terminator(Terminator::Any(b'\n'))
+        .from_path(&args.path)
         .unwrap_or_else(|e| {
-            eprintln!("error: failed to read {:?}: {}", path, e);
+            eprintln!("error: failed to read {:?}: {}", args.path, e);
             std::process::exit(1);
         });
 
     // Initialize the transaction engine.
     let mut engine = TransactionEngine::init();
+    for (currency, precision) in args.precisions {
+        engine.set_precision(currency, precision);
+    }
+    engine.set_cross_client_dispute_policy(args.cross_client_disputes);
+
+    // In dry-run mode, a verdict is written for every row instead of the final balances.
+    let mut verdicts = Vec::new();
+
+    // Optionally, every rejected row is written to a rejects file.
+    // On failure, print an error message and exit the program.
+    let mut rejects = args.rejects.as_ref().map(|path| {
+        Rejects::create(path, args.rejects_format, &args.path).unwrap_or_else(|e| {
+            eprintln!("error: failed to create rejects file {:?}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    let mut summary = Summary::default();
 
     // Deserialize the input CSV file row by row...
-    for row_result in reader.deserialize() {
-        // Parse the current row into a `InputCsvRow` struct.
-        // On failure, print a warning message and continue to next row.
-        let row: InputCsvRow = match row_result {
-            Ok(row) => row,
-            Err(e) => {
-                eprintln!("warning: failed to parse row: {}", e);
-                continue;
-            }
+    let headers = reader.byte_headers().cloned().unwrap_or_default();
+    let mut record = ByteRecord::new();
+    loop {
+        // Read the current row, along with the byte range of its raw text in the input file.
+        let read = reader.read_byte_record(&mut record);
+        let (line, bytes) = match &read {
+            Ok(false) => break,
+            Ok(true) => match record.position() {
+                Some(p) => (p.line(), Some(p.byte()..reader.position().byte())),
+                None => (0, None),
+            },
+            Err(e) => (e.position().map_or(0, |p| p.line()), None),
         };
 
-        // Attempt to process the transaction.
-        let transaction_result = match row.amount {
-            Some(amount) => match row.r#type {
-                TransactionType::Deposit => engine.deposit(row.client, row.tx, amount.0),
-                TransactionType::Withdrawal => engine.withdrawal(row.client, row.tx, amount.0),
-                _ => {
-                    eprintln!("warning: {:?} cannot specify amount", row.r#type);
-                    continue;
+        // Parse the current row into a `InputCsvRow` struct, then into an `Operation`.
+        // On failure, print a warning message (or a verdict) and continue to next row.
+        let operation = read
+            .and_then(|_| record.deserialize::<InputCsvRow>(Some(&headers)))
+            .map_err(|e| format!("failed to parse row: {}", e))
+            .and_then(|row| parse_operation(&engine, row));
+        let operation = match operation {
+            Ok(operation) => operation,
+            Err(warning) => {
+                summary.record(Err(None));
+                if let Some(rejects) = &mut rejects {
+                    rejects.write(line, bytes, None, warning.clone());
                 }
-            },
-            None => match row.r#type {
-                TransactionType::Dispute => engine.dispute(row.client, row.tx),
-                TransactionType::Resolve => engine.resolve(row.client, row.tx),
-                TransactionType::Chargeback => engine.chargeback(row.client, row.tx),
-                _ => {
-                    eprintln!("warning: {:?} must specify amount", row.r#type);
-                    continue;
+                if args.dry_run {
+                    verdicts.push(VerdictCsvRow {
+                        line,
+                        verdict: warning,
+                    });
+                } else {
+                    eprintln!("warning: {}", warning);
                 }
-            },
+                continue;
+            }
         };
 
-        // If the transaction returned an error, print a warning and continue to next row.
-        if let Err(e) = transaction_result {
-            eprintln!("warning: transaction failed: {}", e);
+        // Attempt to process the transaction. In dry-run mode, write its verdict. Otherwise,
+        // print a warning if the transaction was rejected. Either way, continue to next row.
+        // The accepted transactions are processed in dry-run mode too, so that the following
+        // rows are checked against the state they would find.
+        let error = engine.apply(operation).get_error();
+        summary.record(error.map_or(Ok(()), |e| Err(Some(e))));
+        if args.dry_run {
+            let verdict = error.map_or_else(|| String::from("ok"), |e| e.to_string());
+            verdicts.push(VerdictCsvRow { line, verdict });
+        }
+        if let Some(e) = error {
+            if let Some(rejects) = &mut rejects {
+                rejects.write(line, bytes, Some(operation), format!("{:?}", e));
+            }
+            if !args.dry_run {
+                eprintln!("warning: transaction failed: {}", e);
+            }
         }
     }
 
+    // Flush the rejects file, and print the summary of all rows.
+    // On failure, print an error message and exit the program.
+    if let Some(rejects) = &mut rejects {
+        rejects.flush().unwrap_or_else(|e| {
+            eprintln!("error: failed to write rejects file: {}", e);
+            std::process::exit(1);
+        });
+    }
+    summary.print();
+
+    // Write all the verdicts to standard output in dry-run mode.
+    if args.dry_run {
+        let mut writer = Writer::from_writer(std::io::stdout());
+        verdicts
+            .iter()
+            .for_each(|row| writer.serialize(row).unwrap());
+        return;
+    }
+
     // At this point, all input rows (i.e. transactions) have been processed.
-    // Collect all client accounts into a vector of `OutputCsvRow` structs.
+    // Collect all wallets of all client accounts into a vector of `OutputCsvRow` structs.
+    let engine = &engine;
     let mut rows = engine
         .iter_accounts()
-        .map(|(id, account)| OutputCsvRow {
-            client: id,
-            available: Balance(account.get_available_balance()),
-            held: Balance(account.get_held_balance()),
-            total: Balance(account.get_total_balance()),
-            locked: account.is_locked(),
+        .flat_map(|(id, account)| {
+            account
+                .iter_wallets()
+                .map(move |(currency, wallet)| OutputCsvRow {
+                    client: id,
+                    currency,
+                    available: engine.format_balance(currency, wallet.get_available_balance()),
+                    held: engine.format_balance(currency, wallet.get_held_balance()),
+                    total: engine.format_balance(currency, wallet.get_total_balance()),
+                    locked: account.is_locked(),
+                })
         })
         .collect::<Vec<_>>();
 
-    // Sort the rows by client ID to generate deterministic output.
+    // Sort the rows by client ID and currency to generate deterministic output.
     // This is required for the integration tests to work.
-    rows.sort_by_key(|k| k.client);
+    rows.sort_by_key(|k| (k.client, k.currency));
 
     // Write all the rows to standard output.
     let mut writer = Writer::from_writer(std::io::stdout());
@@ -0,0 +1,219 @@
+use crate::{Amount, ClientId, CreditLimit, Currency, TransactionId};
+use serde::{Deserialize, Serialize};
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// The kind of an operation submitted to the transaction engine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperationKind {
+    Deposit,
+    Withdrawal,
+    Transfer,
+    Authorize,
+    Capture,
+    Void,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Unlock,
+    Freeze,
+    Unfreeze,
+    Close,
+    SetCreditLimit,
+}
+
+/// A single operation submitted to the transaction engine.
+///
+/// Every public method of the transaction engine that can modify its state is converted
+/// into an operation, so that accepted operations can be recorded in the journal and
+/// replayed later in the exact same order. Operations can also be submitted directly,
+/// one at a time (see `TransactionEngine::apply`) or as an atomic batch (see
+/// `TransactionEngine::batch`).
+///
+/// An operation can be serialized and deserialized with serde (e.g. as JSON), so that the
+/// same command model can be shared with clients: its kind is in the `type` field, and its
+/// currency can be omitted for the default currency.
+///
+/// ## Example
+/// ```
+/// use transaction_engine::{Currency, Operation};
+///
+/// let json = r#"{"type":"deposit","client":1,"tx":1,"amount":10000}"#;
+/// let operation: Operation = serde_json::from_str(json).unwrap();
+/// let currency = Currency::default();
+/// assert_eq!(Operation::Deposit { client: 1, tx: 1, amount: 1_0000, currency }, operation);
+/// assert_eq!(json, serde_json::to_string(&operation).unwrap());
+/// ```
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Operation {
+    /// See `TransactionEngine::deposit_in`.
+    Deposit {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Currency::is_default")]
+        currency: Currency,
+    },
+    /// See `TransactionEngine::withdrawal_in`.
+    Withdrawal {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Currency::is_default")]
+        currency: Currency,
+    },
+    /// See `TransactionEngine::transfer_in`.
+    Transfer {
+        client: ClientId,
+        tx: TransactionId,
+        to: ClientId,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Currency::is_default")]
+        currency: Currency,
+    },
+    /// See `TransactionEngine::authorize_in`.
+    Authorize {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Currency::is_default")]
+        currency: Currency,
+    },
+    /// See `TransactionEngine::capture_partial`.
+    Capture {
+        client: ClientId,
+        tx: TransactionId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        amount: Option<Amount>,
+    },
+    /// See `TransactionEngine::void`.
+    Void { client: ClientId, tx: TransactionId },
+    /// See `TransactionEngine::dispute_partial`.
+    Dispute {
+        client: ClientId,
+        tx: TransactionId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        amount: Option<Amount>,
+    },
+    /// See `TransactionEngine::resolve_partial`.
+    Resolve {
+        client: ClientId,
+        tx: TransactionId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        amount: Option<Amount>,
+    },
+    /// See `TransactionEngine::chargeback_partial`.
+    Chargeback {
+        client: ClientId,
+        tx: TransactionId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        amount: Option<Amount>,
+    },
+    /// See `TransactionEngine::unlock`.
+    Unlock { client: ClientId },
+    /// See `TransactionEngine::freeze`.
+    Freeze { client: ClientId },
+    /// See `TransactionEngine::unfreeze`.
+    Unfreeze { client: ClientId },
+    /// See `TransactionEngine::close`.
+    Close { client: ClientId },
+    /// See `TransactionEngine::set_credit_limit`.
+    SetCreditLimit {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Currency::is_default")]
+        currency: Currency,
+        limit: CreditLimit,
+    },
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+impl Operation {
+    /// Returns the kind of this operation.
+    pub(crate) fn kind(&self) -> OperationKind {
+        match self {
+            Operation::Deposit { .. } => OperationKind::Deposit,
+            Operation::Withdrawal { .. } => OperationKind::Withdrawal,
+            Operation::Transfer { .. } => OperationKind::Transfer,
+            Operation::Authorize { .. } => OperationKind::Authorize,
+            Operation::Capture { .. } => OperationKind::Capture,
+            Operation::Void { .. } => OperationKind::Void,
+            Operation::Dispute { .. } => OperationKind::Dispute,
+            Operation::Resolve { .. } => OperationKind::Resolve,
+            Operation::Chargeback { .. } => OperationKind::Chargeback,
+            Operation::Unlock { .. } => OperationKind::Unlock,
+            Operation::Freeze { .. } => OperationKind::Freeze,
+            Operation::Unfreeze { .. } => OperationKind::Unfreeze,
+            Operation::Close { .. } => OperationKind::Close,
+            Operation::SetCreditLimit { .. } => OperationKind::SetCreditLimit,
+        }
+    }
+
+    /// Returns the client ID referenced by this operation.
+    pub(crate) fn client(&self) -> ClientId {
+        match *self {
+            Operation::Deposit { client, .. }
+            | Operation::Withdrawal { client, .. }
+            | Operation::Transfer { client, .. }
+            | Operation::Authorize { client, .. }
+            | Operation::Capture { client, .. }
+            | Operation::Void { client, .. }
+            | Operation::Dispute { client, .. }
+            | Operation::Resolve { client, .. }
+            | Operation::Chargeback { client, .. }
+            | Operation::Unlock { client }
+            | Operation::Freeze { client }
+            | Operation::Unfreeze { client }
+            | Operation::Close { client }
+            | Operation::SetCreditLimit { client, .. } => client,
+        }
+    }
+
+    /// Returns the transaction ID referenced by this operation, if any.
+    pub(crate) fn tx(&self) -> Option<TransactionId> {
+        match *self {
+            Operation::Deposit { tx, .. }
+            | Operation::Withdrawal { tx, .. }
+            | Operation::Transfer { tx, .. }
+            | Operation::Authorize { tx, .. }
+            | Operation::Capture { tx, .. }
+            | Operation::Void { tx, .. }
+            | Operation::Dispute { tx, .. }
+            | Operation::Resolve { tx, .. }
+            | Operation::Chargeback { tx, .. } => Some(tx),
+            _ => None,
+        }
+    }
+
+    /// Returns the amount specified by this operation, if any.
+    pub(crate) fn amount(&self) -> Option<Amount> {
+        match *self {
+            Operation::Deposit { amount, .. }
+            | Operation::Withdrawal { amount, .. }
+            | Operation::Transfer { amount, .. }
+            | Operation::Authorize { amount, .. } => Some(amount),
+            Operation::Capture { amount, .. } => amount,
+            Operation::Dispute { amount, .. }
+            | Operation::Resolve { amount, .. }
+            | Operation::Chargeback { amount, .. } => amount,
+            _ => None,
+        }
+    }
+
+    /// Returns the currency specified by this operation, if any.
+    pub(crate) fn currency(&self) -> Option<Currency> {
+        match *self {
+            Operation::Deposit { currency, .. }
+            | Operation::Withdrawal { currency, .. }
+            | Operation::Transfer { currency, .. }
+            | Operation::Authorize { currency, .. }
+            | Operation::SetCreditLimit { currency, .. } => Some(currency),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,47 @@
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// How the transaction engine handles a dispute, resolve, or chargeback whose client ID
+/// differs from the client ID of the transaction it references.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrossClientDisputePolicy {
+    /// Reject it with `WrongClientId` (the default).
+    Reject,
+    /// Accept it and apply it to the client account of its own client ID
+    /// (i.e. the disputing client), which is created if it doesn't already exist.
+    ChargeDisputingClient,
+    /// Accept it and apply it to the client account of the referenced transaction's client ID
+    /// (i.e. the original client).
+    ChargeOriginalClient,
+}
+
+/// How the transaction engine handles a dispute of a withdrawal (or of a captured
+/// authorization, which is treated like a withdrawal).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WithdrawalDisputePolicy {
+    /// Accept it without holding any funds: the balances stay the same. A chargeback credits
+    /// the disputed amount back to the client account (the default).
+    NoHold,
+    /// Reject it with `WithdrawalNotDisputable`.
+    Reject,
+    /// Accept it and hold a pending credit of the disputed amount: the held and total balances
+    /// increase. A resolve cancels the credit, and a chargeback releases it (i.e. the available
+    /// balance increases).
+    HoldCredit,
+}
+
+/// How the transaction engine handles a dispute of a deposit whose amount is greater than the
+/// available balance (e.g. because the deposited funds were already withdrawn).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NegativeBalanceDisputePolicy {
+    /// Accept it and hold the whole disputed amount, even if the available balance becomes
+    /// negative (the default).
+    Allow,
+    /// Reject it with `DisputeExceedsAvailableFunds`.
+    Reject,
+    /// Accept it, but only hold the part of the disputed amount that is available (if any).
+    /// The rest is recorded as a shortfall in the client account, until the dispute is
+    /// resolved or charged back.
+    CapHold,
+}
@@ -0,0 +1,62 @@
+use crate::{Amount, Balance};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+use thiserror::Error;
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// The default number of decimal places of a currency.
+pub const DEFAULT_PRECISION: u32 = 4;
+
+/// The maximum number of decimal places of a currency.
+///
+/// With more decimal places, even an amount of 10 in the smallest unit of the currency
+/// wouldn't fit in an `Amount`.
+pub const MAX_PRECISION: u32 = 18;
+
+/// An error returned when parsing an invalid decimal amount.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum ParseAmountError {
+    #[error("amount is not a valid decimal number")]
+    InvalidNumber,
+    #[error("amount is negative")]
+    Negative,
+    #[error("amount has more than {0} decimal places")]
+    TooPrecise(u32),
+    #[error("amount is too large")]
+    TooLarge,
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+/// Attempts to parse a decimal amount (e.g. "1.5") into the smallest unit of a currency
+/// with the given number of decimal places (e.g. 15000 with 4 decimal places).
+///
+/// Returns an `Error` if the amount has more decimal places than the currency, instead of
+/// silently truncating it. Trailing zeros don't count (e.g. "1.50" is valid with 1 place).
+pub(crate) fn parse_amount(string: &str, precision: u32) -> Result<Amount, ParseAmountError> {
+    let decimal = Decimal::from_str(string)
+        .map_err(|_| ParseAmountError::InvalidNumber)?
+        .normalize();
+    if decimal.is_sign_negative() && !decimal.is_zero() {
+        return Err(ParseAmountError::Negative);
+    }
+    if decimal.scale() > precision {
+        return Err(ParseAmountError::TooPrecise(precision));
+    }
+    10i128
+        .checked_pow(precision - decimal.scale())
+        .and_then(|factor| decimal.mantissa().checked_mul(factor))
+        .and_then(|amount| Amount::try_from(amount).ok())
+        .ok_or(ParseAmountError::TooLarge)
+}
+
+/// Formats a balance in the smallest unit of a currency with the given number of decimal
+/// places (e.g. 15000 is formatted as "1.5000" with 4 decimal places).
+pub(crate) fn format_balance(balance: Balance, precision: u32) -> String {
+    Decimal::new(balance, precision).to_string()
+}
@@ -0,0 +1,390 @@
+use crate::{Client, ClientId, CrossClientDisputePolicy, Event, Operation, Result};
+use crate::{TransactionEngine, TransactionId};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// A transaction engine that partitions client accounts across several shards, each owned by
+/// a worker thread, so that operations of different clients are processed in parallel.
+///
+/// A client account always belongs to the shard `client % shards`, and each operation is routed
+/// to a single shard: the shard of its client, or the shard where the transaction it references
+/// was routed. The operations routed to a shard are processed in the order they were submitted.
+///
+/// Some operations involve client accounts of several shards: a transfer to a client of
+/// another shard, a dispute (or resolve, chargeback, capture, or void) whose client differs from
+/// the referenced transaction's (see `CrossClientDisputePolicy`), and a regular transaction that
+/// reuses the ID of a transaction routed to another shard. In that case, the shard processing
+/// the operation borrows the accounts of the other shards, once they have processed every
+/// previous operation, and gives them back once it's done. These operations are processed
+/// synchronously, so they're much slower than the others.
+///
+/// To route operations, the sharded engine remembers the shard of every transaction ID
+/// it has seen (i.e. ~28 bytes per transaction).
+///
+/// ## Example
+/// ```
+/// use transaction_engine::{CrossClientDisputePolicy, Currency, Operation, ShardedEngine};
+///
+/// let mut engine = ShardedEngine::init(4);
+/// engine.set_cross_client_dispute_policy(CrossClientDisputePolicy::ChargeDisputingClient);
+///
+/// let currency = Currency::default();
+/// engine.submit(Operation::Deposit { client: 1, tx: 1, amount: 100, currency });
+/// engine.submit(Operation::Deposit { client: 2, tx: 2, amount: 50, currency });
+///
+/// // The shard of the transaction borrows client 2's account to process this dispute.
+/// engine.process(Operation::Dispute { client: 2, tx: 1, amount: None }).unwrap();
+///
+/// let accounts = engine.collect_accounts();
+/// assert_eq!(vec![1, 2], accounts.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+/// assert_eq!(100, accounts[0].1.get_available_balance());
+/// assert_eq!(-50, accounts[1].1.get_available_balance());
+/// assert_eq!(100, accounts[1].1.get_held_balance());
+/// ```
+pub struct ShardedEngine {
+    shards: Vec<Shard>,
+    /// The shard each transaction ID was routed to, along with the client IDs of the last
+    /// regular transaction with that ID that may have been accepted.
+    transactions: HashMap<TransactionId, Route>,
+}
+
+impl ShardedEngine {
+    /// Constructs a new sharded engine with the given number of shards (at least 1), each with
+    /// its own worker thread and no history of client accounts or transactions.
+    pub fn init(shards: usize) -> Self {
+        assert!(shards > 0, "a sharded engine needs at least 1 shard");
+        Self {
+            shards: (0..shards).map(|_| Shard::spawn()).collect(),
+            transactions: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of shards.
+    pub fn get_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the shard that owns a client account.
+    pub fn get_shard(&self, client: ClientId) -> usize {
+        usize::from(client) % self.shards.len()
+    }
+
+    /// Sets the cross-client dispute policy of every shard (see
+    /// `TransactionEngine::set_cross_client_dispute_policy`).
+    ///
+    /// It applies to the operations submitted after this method is called.
+    pub fn set_cross_client_dispute_policy(&mut self, policy: CrossClientDisputePolicy) {
+        self.configure(move |engine| engine.set_cross_client_dispute_policy(policy));
+    }
+
+    /// Registers an observer that is called with an `Event` after every processed transaction,
+    /// whether it was accepted or rejected (see `TransactionEngine::subscribe`).
+    ///
+    /// The observer is called from the worker threads of all shards, so events of different
+    /// shards may be observed in any order.
+    pub fn subscribe<F: Fn(&Event) + Send + Sync + 'static>(&mut self, observer: F) {
+        let observer = Arc::new(observer);
+        self.configure(move |engine| {
+            let observer = observer.clone();
+            engine.subscribe(move |event| observer(event));
+        });
+    }
+
+    /// Submits a single operation without waiting for it to be processed.
+    ///
+    /// Its result is only reported to observers. An operation that involves the client accounts
+    /// of several shards is processed before this method returns.
+    pub fn submit(&mut self, operation: Operation) {
+        self.route(operation, false);
+    }
+
+    /// Attempts to process a single operation, and waits for its result.
+    pub fn process(&mut self, operation: Operation) -> Result<()> {
+        // A shard replies to every request with a reply channel.
+        self.route(operation, true).expect("shard didn't reply")
+    }
+
+    /// Returns a copy of a single client account by ID, once its shard has processed every
+    /// operation submitted so far.
+    pub fn get_account(&self, client: ClientId) -> Option<Client> {
+        let (sender, receiver) = channel();
+        self.shards[self.get_shard(client)].send(Message::Account(client, sender));
+        receive(receiver)
+    }
+
+    /// Returns a copy of all client accounts (i.e. the merged view of all shards) sorted by ID,
+    /// once every shard has processed every operation submitted so far.
+    pub fn collect_accounts(&self) -> Vec<(ClientId, Client)> {
+        let receivers = self
+            .shards
+            .iter()
+            .map(|shard| {
+                let (sender, receiver) = channel();
+                shard.send(Message::Accounts(sender));
+                receiver
+            })
+            .collect::<Vec<_>>();
+        let mut accounts = receivers.into_iter().flat_map(receive).collect::<Vec<_>>();
+        accounts.sort_unstable_by_key(|&(id, _)| id);
+        accounts
+    }
+}
+
+impl Drop for ShardedEngine {
+    /// Waits for every shard to process the operations submitted so far, and stops them.
+    fn drop(&mut self) {
+        for shard in &mut self.shards {
+            shard.stop();
+        }
+    }
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+type Accounts = Vec<(ClientId, Option<Client>)>;
+type Configure = Box<dyn FnOnce(&mut TransactionEngine) + Send>;
+
+/// The shard a transaction ID was routed to, and the client IDs involved in the transaction.
+struct Route {
+    shard: usize,
+    client: ClientId,
+    recipient: Option<ClientId>,
+}
+
+/// A request to a worker thread.
+enum Message {
+    /// Process an operation, and reply with its result if requested.
+    Operation(Operation, Option<Sender<Result<()>>>),
+    /// Lend client accounts to another shard.
+    Lend(Vec<ClientId>, Sender<Accounts>),
+    /// Process an operation with borrowed client accounts, and reply with its result
+    /// and with the borrowed accounts (which are removed from this shard).
+    Borrow(Operation, Accounts, Sender<(Result<()>, Accounts)>),
+    /// Take back client accounts that were lent to another shard.
+    GiveBack(Accounts),
+    Account(ClientId, Sender<Option<Client>>),
+    Accounts(Sender<Vec<(ClientId, Client)>>),
+    Configure(Configure),
+}
+
+/// A worker thread that owns the transaction engine of a shard.
+struct Shard {
+    sender: Option<Sender<Message>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Shard {
+    fn spawn() -> Self {
+        let (sender, receiver) = channel();
+        let thread = std::thread::spawn(move || run(TransactionEngine::init(), receiver));
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    fn send(&self, message: Message) {
+        // The worker thread only stops once the sender is dropped, which only happens on drop.
+        let sender = self.sender.as_ref().expect("shard worker thread stopped");
+        sender.send(message).expect("shard worker thread stopped");
+    }
+
+    fn stop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            // Don't panic in `drop` if the worker thread has already panicked.
+            let _ = thread.join();
+        }
+    }
+}
+
+impl ShardedEngine {
+    /// Sends a configuration closure to every shard.
+    fn configure<F: Fn(&mut TransactionEngine) + Send + Sync + 'static>(&mut self, f: F) {
+        let f = Arc::new(f);
+        for shard in &self.shards {
+            let f = f.clone();
+            shard.send(Message::Configure(Box::new(move |engine| f(engine))));
+        }
+    }
+
+    /// Routes an operation to a shard, and returns its result if `wait` is true or if it was
+    /// processed synchronously.
+    fn route(&mut self, operation: Operation, wait: bool) -> Option<Result<()>> {
+        let client = operation.client();
+        let (shard, clients) = match operation {
+            // A new regular transaction is routed to its client's shard. If its ID was already
+            // routed to another shard, it's routed there too, to be checked for duplicates.
+            Operation::Deposit { tx, .. }
+            | Operation::Withdrawal { tx, .. }
+            | Operation::Transfer { tx, .. }
+            | Operation::Authorize { tx, .. } => {
+                let recipient = match operation {
+                    Operation::Transfer { to, .. } => Some(to),
+                    _ => None,
+                };
+                let route = Route {
+                    shard: self.get_shard(client),
+                    client,
+                    recipient,
+                };
+                let shard = match self.transactions.get(&tx) {
+                    // Only update the client IDs if it's accepted. Otherwise, the previous
+                    // transaction with this ID may have been accepted.
+                    Some(previous) => {
+                        let shard = previous.shard;
+                        let clients = [Some(client), recipient, None];
+                        let result = self.process_on(shard, operation, clients);
+                        if result.is_ok() {
+                            self.transactions.insert(tx, Route { shard, ..route });
+                        }
+                        return Some(result);
+                    }
+                    None => route.shard,
+                };
+                self.transactions.insert(tx, route);
+                (shard, [Some(client), recipient, None])
+            }
+            // An operation that references a transaction is routed to the transaction's shard.
+            // It may involve its own client account, and the accounts of the transaction.
+            Operation::Capture { tx, .. }
+            | Operation::Void { tx, .. }
+            | Operation::Dispute { tx, .. }
+            | Operation::Resolve { tx, .. }
+            | Operation::Chargeback { tx, .. } => match self.transactions.get(&tx) {
+                Some(route) => (
+                    route.shard,
+                    [Some(client), Some(route.client), route.recipient],
+                ),
+                None => (self.get_shard(client), [Some(client), None, None]),
+            },
+            Operation::Unlock { .. }
+            | Operation::Freeze { .. }
+            | Operation::Unfreeze { .. }
+            | Operation::Close { .. }
+            | Operation::SetCreditLimit { .. } => {
+                (self.get_shard(client), [Some(client), None, None])
+            }
+        };
+
+        // Process the operation synchronously if it involves the accounts of other shards.
+        if clients
+            .iter()
+            .flatten()
+            .any(|&id| self.get_shard(id) != shard)
+        {
+            return Some(self.process_on(shard, operation, clients));
+        }
+
+        if !wait {
+            self.shards[shard].send(Message::Operation(operation, None));
+            return None;
+        }
+        let (sender, receiver) = channel();
+        self.shards[shard].send(Message::Operation(operation, Some(sender)));
+        Some(receive(receiver))
+    }
+
+    /// Processes an operation on the given shard, which borrows the given client accounts
+    /// from the other shards for the duration of the operation.
+    fn process_on(
+        &self,
+        shard: usize,
+        operation: Operation,
+        clients: [Option<ClientId>; 3],
+    ) -> Result<()> {
+        // Borrow the accounts once their shards have processed every previous operation.
+        let mut lenders = HashMap::<usize, Vec<ClientId>>::new();
+        for id in clients.iter().flatten().copied() {
+            let lender = self.get_shard(id);
+            if lender != shard {
+                let ids = lenders.entry(lender).or_default();
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        let mut borrowed = Vec::new();
+        for (lender, ids) in lenders {
+            let (sender, receiver) = channel();
+            self.shards[lender].send(Message::Lend(ids, sender));
+            borrowed.extend(receive(receiver));
+        }
+
+        // Process the operation, and give the accounts back. Since the shards process their
+        // messages in order, they get their accounts back before any later operation.
+        let (sender, receiver) = channel();
+        self.shards[shard].send(Message::Borrow(operation, borrowed, sender));
+        let (result, borrowed) = receive(receiver);
+        let mut lent = HashMap::<usize, Accounts>::new();
+        for (id, account) in borrowed {
+            lent.entry(self.get_shard(id))
+                .or_default()
+                .push((id, account));
+        }
+        for (lender, accounts) in lent {
+            self.shards[lender].send(Message::GiveBack(accounts));
+        }
+        result
+    }
+}
+
+/// Processes the messages sent to a shard until its sender is dropped.
+fn run(mut engine: TransactionEngine, receiver: Receiver<Message>) {
+    while let Ok(message) = receiver.recv() {
+        match message {
+            Message::Operation(operation, reply) => {
+                let result = engine.process(operation);
+                if let Some(reply) = reply {
+                    let _ = reply.send(result);
+                }
+            }
+            Message::Lend(clients, reply) => {
+                let accounts = clients
+                    .into_iter()
+                    .map(|id| (id, engine.take_account(id)))
+                    .collect();
+                let _ = reply.send(accounts);
+            }
+            Message::Borrow(operation, accounts, reply) => {
+                let ids = accounts.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+                for (id, account) in accounts {
+                    engine.put_account(id, account);
+                }
+                let result = engine.process(operation);
+                let accounts = ids
+                    .into_iter()
+                    .map(|id| (id, engine.take_account(id)))
+                    .collect();
+                let _ = reply.send((result, accounts));
+            }
+            Message::GiveBack(accounts) => {
+                for (id, account) in accounts {
+                    engine.put_account(id, account);
+                }
+            }
+            Message::Account(client, reply) => {
+                let _ = reply.send(engine.get_account(client).cloned());
+            }
+            Message::Accounts(reply) => {
+                let accounts = engine.iter_accounts();
+                let _ = reply.send(accounts.map(|(id, client)| (id, client.clone())).collect());
+            }
+            Message::Configure(configure) => configure(&mut engine),
+        }
+    }
+}
+
+/// Waits for the reply of a shard.
+fn receive<T>(receiver: Receiver<T>) -> T {
+    // A shard replies to every request, unless its worker thread panicked.
+    receiver.recv().expect("shard worker thread stopped")
+}
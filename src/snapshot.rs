@@ -0,0 +1,157 @@
+use crate::{Balance, Client, ClientId, Currency, Expiry, Transaction, TransactionId};
+use crate::{TransactionKind, TransactionState};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+/// The version of the snapshot file format.
+///
+/// It *must* be incremented whenever the serialized representation of a record changes,
+/// so that a snapshot written by an older version is rejected instead of misinterpreted.
+const SNAPSHOT_VERSION: u32 = 11;
+
+/// The complete state of a transaction engine at a point in time.
+pub(crate) struct Snapshot {
+    pub(crate) journal_offset: u64,
+    pub(crate) expiry: Expiry,
+    pub(crate) clients: HashMap<ClientId, Client>,
+    pub(crate) transactions: HashMap<TransactionId, Transaction>,
+}
+
+/// The first line of a snapshot file.
+#[derive(Deserialize, Serialize)]
+struct Header {
+    version: u32,
+    journal_offset: u64,
+}
+
+/// Every line of a snapshot file after the header and the expiry state.
+///
+/// The type parameters allow serializing borrowed records and deserializing owned records.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "record", rename_all = "lowercase")]
+enum Record<C, T> {
+    Client { id: ClientId, client: C },
+    Transaction { id: TransactionId, transaction: T },
+}
+
+/// The serialized representation of a transaction (which isn't serializable itself,
+/// so that its fields stay private outside this crate).
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "Transaction")]
+struct TransactionDef {
+    amount: Balance,
+    client: ClientId,
+    recipient: Option<ClientId>,
+    currency: Currency,
+    kind: TransactionKind,
+    state: TransactionState,
+    held: Balance,
+    reversed: Balance,
+    shortfall: Balance,
+    credit: bool,
+}
+
+/// A transaction serialized as a `TransactionDef`.
+#[derive(Deserialize, Serialize)]
+struct TransactionRecord(#[serde(with = "TransactionDef")] Transaction);
+
+/// Writes a snapshot to the given path, one JSON object per line: the header, the state
+/// required to expire transactions, and then one line per client account or transaction.
+///
+/// The snapshot is first written to a temporary file which is then renamed,
+/// so that an existing snapshot at that path is never left half-overwritten.
+pub(crate) fn write<'a, P, C, T>(
+    path: P,
+    journal_offset: u64,
+    expiry: &Expiry,
+    clients: C,
+    transactions: T,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    C: Iterator<Item = (ClientId, &'a Client)>,
+    T: Iterator<Item = Result<(TransactionId, Transaction)>>,
+{
+    let path = path.as_ref();
+    let temp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+
+    let header = Header {
+        version: SNAPSHOT_VERSION,
+        journal_offset,
+    };
+    write_line(&mut writer, &header)?;
+    write_line(&mut writer, expiry)?;
+    for (id, client) in clients {
+        let record = Record::<_, ()>::Client { id, client };
+        write_line(&mut writer, &record)?;
+    }
+    for transaction in transactions {
+        let (id, transaction) = transaction?;
+        let transaction = TransactionRecord(transaction);
+        let record = Record::<(), _>::Transaction { id, transaction };
+        write_line(&mut writer, &record)?;
+    }
+
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    std::fs::rename(temp_path, path)
+}
+
+/// Reads a snapshot from the given path.
+///
+/// Returns an `Error` if the file can't be read, if it was written with a different version
+/// of the snapshot file format, or if one of its lines is invalid.
+pub(crate) fn read<P: AsRef<Path>>(path: P) -> Result<Snapshot> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+
+    let header: Header = match lines.next() {
+        Some(line) => parse_line(&line?)?,
+        None => return Err(Error::new(ErrorKind::InvalidData, "empty snapshot")),
+    };
+    if header.version != SNAPSHOT_VERSION {
+        let message = format!("unsupported snapshot version {}", header.version);
+        return Err(Error::new(ErrorKind::InvalidData, message));
+    }
+
+    let expiry: Expiry = match lines.next() {
+        Some(line) => parse_line(&line?)?,
+        None => return Err(Error::new(ErrorKind::InvalidData, "truncated snapshot")),
+    };
+
+    let mut snapshot = Snapshot {
+        journal_offset: header.journal_offset,
+        expiry,
+        clients: HashMap::new(),
+        transactions: HashMap::new(),
+    };
+    for line in lines {
+        match parse_line::<Record<Client, TransactionRecord>>(&line?)? {
+            Record::Client { id, client } => {
+                snapshot.clients.insert(id, client);
+            }
+            Record::Transaction { id, transaction } => {
+                snapshot.transactions.insert(id, transaction.0);
+            }
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Serializes a value as a single line of JSON.
+fn write_line<W: Write, S: Serialize>(writer: &mut W, value: &S) -> Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writer.write_all(b"\n")
+}
+
+/// Deserializes a value from a single line of JSON.
+fn parse_line<'de, D: Deserialize<'de>>(line: &'de str) -> Result<D> {
+    serde_json::from_str(line).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,225 @@
+use crate::{Client, ClientId, Transaction, TransactionId};
+use std::collections::HashMap;
+use std::io::Result;
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// The storage of the client accounts and cached transactions of the transaction engine.
+///
+/// It's implemented by `HashMapStorage` (the default) and `VecStorage`, and the backend of a
+/// transaction engine is chosen when it's constructed (see `TransactionEngine::with_storage`).
+///
+/// Transactions are read and written by value: the engine modifies a copy of a transaction
+/// and only writes it back once the operation referencing it has succeeded.
+///
+/// Client accounts are always kept in memory, but transactions may be stored elsewhere (e.g. on
+/// disk with `DiskStorage`), so the methods to access them return an `Error` if the storage
+/// fails. A method that returns an `Error` *must* leave the storage unmodified, so that the
+/// engine can reject the operation that accessed it.
+pub trait Storage: Send {
+    /// Returns a single client account by ID.
+    fn get_client(&self, id: ClientId) -> Option<&Client>;
+
+    /// Returns a single client account by ID, to modify it in place.
+    fn get_client_mut(&mut self, id: ClientId) -> Option<&mut Client>;
+
+    /// Inserts a client account, replacing the existing account with the same ID (if any).
+    fn insert_client(&mut self, id: ClientId, client: Client);
+
+    /// Removes a client account by ID (if it exists).
+    fn remove_client(&mut self, id: ClientId);
+
+    /// Returns an iterator over all client accounts in arbitrary order.
+    fn iter_clients(&self) -> Box<dyn Iterator<Item = (ClientId, &Client)> + '_>;
+
+    /// Returns a copy of a single cached transaction by ID.
+    fn get_transaction(&self, tx: TransactionId) -> Result<Option<Transaction>>;
+
+    /// Inserts a transaction, replacing the existing transaction with the same ID (if any).
+    fn insert_transaction(&mut self, tx: TransactionId, transaction: Transaction) -> Result<()>;
+
+    /// Removes a transaction by ID (if it exists).
+    fn remove_transaction(&mut self, tx: TransactionId) -> Result<()>;
+
+    /// Returns an iterator over all cached transactions in arbitrary order.
+    fn iter_transactions(&self) -> TransactionIter<'_>;
+
+    /// Removes all client accounts and transactions.
+    fn clear(&mut self) -> Result<()>;
+}
+
+/// An iterator over the cached transactions of a `Storage`.
+pub type TransactionIter<'a> = Box<dyn Iterator<Item = Result<(TransactionId, Transaction)>> + 'a>;
+
+/// A storage backed by two hash maps.
+///
+/// Its memory usage is proportional to the number of client accounts and cached transactions,
+/// whatever their IDs.
+#[derive(Default)]
+pub struct HashMapStorage {
+    clients: HashMap<ClientId, Client>,
+    transactions: HashMap<TransactionId, Transaction>,
+}
+
+impl HashMapStorage {
+    /// Constructs a new empty storage.
+    pub fn init() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for HashMapStorage {
+    fn get_client(&self, id: ClientId) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    fn get_client_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+        self.clients.get_mut(&id)
+    }
+
+    fn insert_client(&mut self, id: ClientId, client: Client) {
+        self.clients.insert(id, client);
+    }
+
+    fn remove_client(&mut self, id: ClientId) {
+        self.clients.remove(&id);
+    }
+
+    fn iter_clients(&self) -> Box<dyn Iterator<Item = (ClientId, &Client)> + '_> {
+        Box::new(self.clients.iter().map(|(&id, client)| (id, client)))
+    }
+
+    fn get_transaction(&self, tx: TransactionId) -> Result<Option<Transaction>> {
+        Ok(self.transactions.get(&tx).copied())
+    }
+
+    fn insert_transaction(&mut self, tx: TransactionId, transaction: Transaction) -> Result<()> {
+        self.transactions.insert(tx, transaction);
+        Ok(())
+    }
+
+    fn remove_transaction(&mut self, tx: TransactionId) -> Result<()> {
+        self.transactions.remove(&tx);
+        Ok(())
+    }
+
+    fn iter_transactions(&self) -> TransactionIter<'_> {
+        let iter = self.transactions.iter();
+        Box::new(iter.map(|(&tx, &transaction)| Ok((tx, transaction))))
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.clients.clear();
+        self.transactions.clear();
+        Ok(())
+    }
+}
+
+/// A storage backed by two vectors indexed by ID, which is much faster than `HashMapStorage`
+/// (see the benchmark in the README).
+///
+/// Each vector grows up to the largest ID stored in it, so its memory usage is proportional
+/// to the largest client ID (~1.6 MB at most) and to the largest transaction ID (up to ~200 GB
+/// for `TransactionId::MAX`). It's therefore best suited to dense transaction IDs starting
+/// from 0, ideally along with a dispute window to bound the number of cached transactions.
+#[derive(Default)]
+pub struct VecStorage {
+    clients: Vec<Option<Client>>,
+    transactions: Vec<Option<Transaction>>,
+}
+
+impl VecStorage {
+    /// Constructs a new empty storage.
+    pub fn init() -> Self {
+        Self::default()
+    }
+
+    /// Constructs a new empty storage with room for every client ID and for the given number
+    /// of transaction IDs (starting from 0) allocated ahead of time.
+    pub fn with_capacity(transactions: usize) -> Self {
+        Self {
+            clients: vec![None; usize::from(ClientId::MAX) + 1],
+            transactions: vec![None; transactions],
+        }
+    }
+}
+
+impl Storage for VecStorage {
+    fn get_client(&self, id: ClientId) -> Option<&Client> {
+        self.clients.get(usize::from(id))?.as_ref()
+    }
+
+    fn get_client_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+        self.clients.get_mut(usize::from(id))?.as_mut()
+    }
+
+    fn insert_client(&mut self, id: ClientId, client: Client) {
+        *slot(&mut self.clients, usize::from(id)) = Some(client);
+    }
+
+    fn remove_client(&mut self, id: ClientId) {
+        if let Some(client) = self.clients.get_mut(usize::from(id)) {
+            *client = None;
+        }
+    }
+
+    fn iter_clients(&self) -> Box<dyn Iterator<Item = (ClientId, &Client)> + '_> {
+        let iter = self.clients.iter().enumerate();
+        // The index of a client account is always converted from a `ClientId`.
+        Box::new(iter.filter_map(|(id, client)| Some((id as ClientId, client.as_ref()?))))
+    }
+
+    fn get_transaction(&self, tx: TransactionId) -> Result<Option<Transaction>> {
+        Ok(self.transactions.get(index(tx)).copied().flatten())
+    }
+
+    fn insert_transaction(&mut self, tx: TransactionId, transaction: Transaction) -> Result<()> {
+        *slot(&mut self.transactions, index(tx)) = Some(transaction);
+        Ok(())
+    }
+
+    fn remove_transaction(&mut self, tx: TransactionId) -> Result<()> {
+        if let Some(transaction) = self.transactions.get_mut(index(tx)) {
+            *transaction = None;
+        }
+        Ok(())
+    }
+
+    fn iter_transactions(&self) -> TransactionIter<'_> {
+        let iter = self.transactions.iter().enumerate();
+        // The index of a transaction is always converted from a `TransactionId`.
+        Box::new(
+            iter.filter_map(|(tx, &transaction)| Some(Ok((tx as TransactionId, transaction?)))),
+        )
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        // Keep the allocated capacity, but drop every client account and transaction.
+        self.clients.iter_mut().for_each(|client| *client = None);
+        self.transactions
+            .iter_mut()
+            .for_each(|transaction| *transaction = None);
+        Ok(())
+    }
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+/// Converts a transaction ID into an index of `VecStorage`.
+fn index(tx: TransactionId) -> usize {
+    // A `usize` is at least 32 bits on all supported platforms.
+    usize::try_from(tx).expect("transaction id doesn't fit in a usize")
+}
+
+/// Returns the slot at the given index of a vector, which is first extended with `None` values
+/// if it's too short.
+fn slot<T>(vec: &mut Vec<Option<T>>, index: usize) -> &mut Option<T> {
+    if vec.len() <= index {
+        vec.resize_with(index + 1, || None);
+    }
+    &mut vec[index]
+}
@@ -1,4 +1,6 @@
-use crate::{Amount, Balance, ClientId, Result, TransactionError};
+use crate::{Amount, Balance, ClientId, Currency, Result, TransactionError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
 /******************************************
  *               PUBLIC API               *
@@ -7,97 +9,480 @@ use crate::{Amount, Balance, ClientId, Result, TransactionError};
 /// A unique 32-bit ID for a transaction.
 pub type TransactionId = u32;
 
-/*******************************************
- *               PRIVATE API               *
- *******************************************/
+/// A regular transaction kind.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+    /// A transfer from the client account of the transaction to another client account.
+    Transfer,
+    /// An authorization that holds funds until it's captured (like a withdrawal) or voided.
+    Authorization,
+}
+
+/// A transaction state.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionState {
+    /// Accepted when a new regular transaction is successfully processed
+    /// or when no part of its amount is disputed anymore after a resolve or a chargeback.
+    Accepted,
+    /// Disputed when part or all of its amount is referenced by a dispute transaction,
+    /// until that part is referenced by a resolve or chargeback transaction.
+    Disputed,
+    /// Reversed when all of its amount was referenced by chargeback transactions.
+    /// FINAL: the state can't change at this point and the associated account is locked
+    /// (although it can be unlocked later by an admin).
+    Reversed,
+    /// Authorized when a new authorization is successfully processed, until it's captured
+    /// (then it's accepted and can be disputed like a withdrawal) or voided.
+    Authorized,
+    /// Voided when an authorized transaction is voided and its held funds are released.
+    /// FINAL: the state can't change at this point.
+    Voided,
+}
+
+/// A read-only view of a regular transaction cached by the transaction engine.
+#[derive(Clone, Copy)]
+pub struct TransactionView {
+    id: TransactionId,
+    transaction: Transaction,
+}
 
-/// A single regular transaction (i.e. deposit or withdrawal).
-pub(crate) struct Transaction {
+impl TransactionView {
+    /// Returns the ID of this transaction.
+    pub fn get_id(&self) -> TransactionId {
+        self.id
+    }
+
+    /// Returns the client ID of this transaction.
+    pub fn get_client(&self) -> ClientId {
+        self.transaction.client
+    }
+
+    /// Returns the amount of this transaction.
+    ///
+    /// For an authorization, this is the authorized amount until it's captured,
+    /// and then the captured amount.
+    pub fn get_amount(&self) -> Amount {
+        // The amount was converted from an `Amount` when this transaction was constructed,
+        // so it's guaranteed to be non-negative.
+        self.transaction.amount as Amount
+    }
+
+    /// Returns the recipient's client ID of this transaction if it's a transfer.
+    pub fn get_recipient(&self) -> Option<ClientId> {
+        self.transaction.recipient
+    }
+
+    /// Returns the currency of this transaction.
+    pub fn get_currency(&self) -> Currency {
+        self.transaction.currency
+    }
+
+    /// Returns the kind of this transaction.
+    pub fn get_kind(&self) -> TransactionKind {
+        self.transaction.kind
+    }
+
+    /// Returns the state of this transaction.
+    pub fn get_state(&self) -> TransactionState {
+        self.transaction.state
+    }
+
+    /// Returns the part of the amount of this transaction that is currently disputed.
+    pub fn get_disputed_amount(&self) -> Amount {
+        self.transaction.held as Amount
+    }
+
+    /// Returns the part of the amount of this transaction that was reversed by chargebacks.
+    pub fn get_reversed_amount(&self) -> Amount {
+        self.transaction.reversed as Amount
+    }
+
+    /// Returns the part of the disputed amount of this transaction that couldn't be held
+    /// because it exceeded the available balance (see `NegativeBalanceDisputePolicy`).
+    pub fn get_shortfall(&self) -> Amount {
+        self.transaction.shortfall as Amount
+    }
+}
+
+impl fmt::Debug for TransactionView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransactionView")
+            .field("id", &self.get_id())
+            .field("client", &self.get_client())
+            .field("amount", &self.get_amount())
+            .field("recipient", &self.get_recipient())
+            .field("currency", &self.get_currency())
+            .field("kind", &self.get_kind())
+            .field("state", &self.get_state())
+            .field("disputed_amount", &self.get_disputed_amount())
+            .field("reversed_amount", &self.get_reversed_amount())
+            .field("shortfall", &self.get_shortfall())
+            .finish()
+    }
+}
+
+/// A single regular transaction (i.e. deposit, withdrawal, transfer, or authorization),
+/// as cached by the transaction engine in its `Storage`.
+///
+/// It's only public so that `Storage` can be implemented outside this crate. It's opaque:
+/// a storage backend can only copy it, or convert it to and from bytes to persist it
+/// (see `to_bytes`). Use `TransactionView` to inspect a cached transaction.
+///
+/// ## Stability
+/// The byte representation of this type is NOT part of the stable API, and it may change in
+/// any release. A storage backend that persists transactions *must* not read them back with
+/// another version of this crate.
+#[derive(Clone, Copy)]
+pub struct Transaction {
     pub(crate) amount: Balance,
     pub(crate) client: ClientId,
+    /// The client ID of the recipient if this is a transfer (the sender is `client`).
+    pub(crate) recipient: Option<ClientId>,
+    pub(crate) currency: Currency,
     pub(crate) kind: TransactionKind,
     pub(crate) state: TransactionState,
+    /// The part of the amount that is currently disputed (i.e. held for a deposit).
+    pub(crate) held: Balance,
+    /// The part of the amount that was reversed by chargebacks.
+    pub(crate) reversed: Balance,
+    /// The part of the disputed amount that couldn't be held (see `NegativeBalanceDisputePolicy`).
+    pub(crate) shortfall: Balance,
+    /// Whether the disputed amount of this withdrawal (or captured authorization) is held as
+    /// a pending credit, according to the `WithdrawalDisputePolicy` it was disputed with.
+    pub(crate) credit: bool,
 }
 
+impl Transaction {
+    /// The length of the byte representation of a transaction.
+    pub const ENCODED_LENGTH: usize = 48;
+
+    /// Returns the byte representation of this transaction: its fields in little-endian order.
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LENGTH] {
+        let mut bytes = [0; Self::ENCODED_LENGTH];
+        bytes[0..8].copy_from_slice(&self.amount.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.client.to_le_bytes());
+        if let Some(recipient) = self.recipient {
+            bytes[10] = 1;
+            bytes[11..13].copy_from_slice(&recipient.to_le_bytes());
+        }
+        bytes[13..21].copy_from_slice(&self.currency.to_bytes());
+        bytes[21] = match self.kind {
+            TransactionKind::Deposit => 0,
+            TransactionKind::Withdrawal => 1,
+            TransactionKind::Transfer => 2,
+            TransactionKind::Authorization => 3,
+        };
+        bytes[22] = match self.state {
+            TransactionState::Accepted => 0,
+            TransactionState::Disputed => 1,
+            TransactionState::Reversed => 2,
+            TransactionState::Authorized => 3,
+            TransactionState::Voided => 4,
+        };
+        bytes[23..31].copy_from_slice(&self.held.to_le_bytes());
+        bytes[31..39].copy_from_slice(&self.reversed.to_le_bytes());
+        bytes[39..47].copy_from_slice(&self.shortfall.to_le_bytes());
+        bytes[47] = u8::from(self.credit);
+        bytes
+    }
+
+    /// Constructs a transaction from bytes returned by `to_bytes`.
+    ///
+    /// Returns `None` if the bytes aren't the representation of a transaction.
+    pub fn from_bytes(bytes: &[u8; Self::ENCODED_LENGTH]) -> Option<Self> {
+        let i64_at = |i: usize| {
+            let mut field = [0; 8];
+            field.copy_from_slice(&bytes[i..i + 8]);
+            i64::from_le_bytes(field)
+        };
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let mut currency = [0; 8];
+        currency.copy_from_slice(&bytes[13..21]);
+        Some(Self {
+            amount: i64_at(0),
+            client: u16_at(8),
+            recipient: match bytes[10] {
+                0 => None,
+                1 => Some(u16_at(11)),
+                _ => return None,
+            },
+            currency: Currency::from_bytes(currency),
+            kind: match bytes[21] {
+                0 => TransactionKind::Deposit,
+                1 => TransactionKind::Withdrawal,
+                2 => TransactionKind::Transfer,
+                3 => TransactionKind::Authorization,
+                _ => return None,
+            },
+            state: match bytes[22] {
+                0 => TransactionState::Accepted,
+                1 => TransactionState::Disputed,
+                2 => TransactionState::Reversed,
+                3 => TransactionState::Authorized,
+                4 => TransactionState::Voided,
+                _ => return None,
+            },
+            held: i64_at(23),
+            reversed: i64_at(31),
+            shortfall: i64_at(39),
+            credit: match bytes[47] {
+                0 => false,
+                1 => true,
+                _ => return None,
+            },
+        })
+    }
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
 /// A single special transaction (i.e. dispute, resolve, or chargeback).
 pub(crate) struct SpecialTransaction {
+    /// The part of the referenced transaction's amount, or `None` for the whole amount
+    /// (i.e. all of its undisputed amount for a dispute, or all of its disputed amount
+    /// for a resolve or a chargeback).
+    pub(crate) amount: Option<Balance>,
     pub(crate) client: ClientId,
     pub(crate) kind: SpecialTransactionKind,
     pub(crate) tx: TransactionId,
 }
 
-pub(crate) enum TransactionKind {
-    Deposit,
-    Withdrawal,
-}
-
 pub(crate) enum SpecialTransactionKind {
     Dispute,
     Resolve,
     Chargeback,
 }
 
-/// A transaction state.
-pub(crate) enum TransactionState {
-    /// Accepted when a new regular transaction is successfully processed
-    /// or when a disputed transaction is referenced by a resolve transaction.
-    Accepted,
-    /// Disputed when an accepted transaction is referenced by a dispute transaction.
-    Disputed,
-    /// Reversed when a disputed transaction is referenced by a chargeback transaction.
-    /// FINAL: the state can't change at this point and the associated account is locked.
-    Reversed,
+/// A single settlement of an authorization (i.e. capture or void).
+pub(crate) struct Settlement {
+    /// The captured part of the authorized amount, or `None` for the whole amount.
+    pub(crate) amount: Option<Balance>,
+    pub(crate) client: ClientId,
+    pub(crate) kind: SettlementKind,
+    pub(crate) tx: TransactionId,
+}
+
+pub(crate) enum SettlementKind {
+    Capture,
+    Void,
 }
 
 impl Transaction {
     /// Attempts to construct a new deposit transaction.
-    pub(crate) fn try_new_deposit(client: ClientId, amount: Amount) -> Result<Self> {
+    pub(crate) fn try_new_deposit(
+        client: ClientId,
+        currency: Currency,
+        amount: Amount,
+    ) -> Result<Self> {
         Ok(Self {
             amount: try_convert_u64_to_i64(amount)?,
             client,
+            recipient: None,
+            currency,
             kind: TransactionKind::Deposit,
             state: TransactionState::Accepted,
+            held: 0,
+            reversed: 0,
+            shortfall: 0,
+            credit: false,
         })
     }
 
     /// Attempts to construct a new withdrawal transaction.
-    pub(crate) fn try_new_withdrawal(client: ClientId, amount: Amount) -> Result<Self> {
+    pub(crate) fn try_new_withdrawal(
+        client: ClientId,
+        currency: Currency,
+        amount: Amount,
+    ) -> Result<Self> {
         Ok(Self {
             amount: try_convert_u64_to_i64(amount)?,
             client,
+            recipient: None,
+            currency,
             kind: TransactionKind::Withdrawal,
             state: TransactionState::Accepted,
+            held: 0,
+            reversed: 0,
+            shortfall: 0,
+            credit: false,
         })
     }
+
+    /// Attempts to construct a new transfer transaction.
+    pub(crate) fn try_new_transfer(
+        client: ClientId,
+        recipient: ClientId,
+        currency: Currency,
+        amount: Amount,
+    ) -> Result<Self> {
+        if client == recipient {
+            return Err(TransactionError::TransferToSameClient);
+        }
+        Ok(Self {
+            amount: try_convert_u64_to_i64(amount)?,
+            client,
+            recipient: Some(recipient),
+            currency,
+            kind: TransactionKind::Transfer,
+            state: TransactionState::Accepted,
+            held: 0,
+            reversed: 0,
+            shortfall: 0,
+            credit: false,
+        })
+    }
+
+    /// Attempts to construct a new authorization transaction.
+    pub(crate) fn try_new_authorization(
+        client: ClientId,
+        currency: Currency,
+        amount: Amount,
+    ) -> Result<Self> {
+        Ok(Self {
+            amount: try_convert_u64_to_i64(amount)?,
+            client,
+            recipient: None,
+            currency,
+            kind: TransactionKind::Authorization,
+            state: TransactionState::Authorized,
+            held: 0,
+            reversed: 0,
+            shortfall: 0,
+            credit: false,
+        })
+    }
+
+    /// Returns the part of the amount that is neither disputed nor reversed.
+    pub(crate) fn get_undisputed_amount(&self) -> Balance {
+        // The client account *must* guarantee that `held + reversed <= amount`.
+        self.amount - self.held - self.reversed
+    }
+
+    /// Updates the state of this transaction after a resolve or a chargeback.
+    pub(crate) fn update_state(&mut self) {
+        self.state = if self.reversed == self.amount {
+            TransactionState::Reversed
+        } else if self.held > 0 {
+            TransactionState::Disputed
+        } else {
+            TransactionState::Accepted
+        };
+    }
+
+    /// Returns whether this transaction is pending (i.e. disputed or authorized), in which case
+    /// it must stay in the cache even after it has expired, until it's resolved or settled.
+    pub(crate) fn is_pending(&self) -> bool {
+        matches!(
+            self.state,
+            TransactionState::Disputed | TransactionState::Authorized
+        )
+    }
+}
+
+impl TransactionView {
+    /// Constructs a new read-only view of a transaction.
+    pub(crate) fn new(id: TransactionId, transaction: Transaction) -> Self {
+        Self { id, transaction }
+    }
 }
 
 impl SpecialTransaction {
-    /// Constructs a new dispute transaction.
-    pub(crate) fn new_dispute(client: ClientId, tx: TransactionId) -> Self {
-        Self {
+    /// Attempts to construct a new dispute transaction.
+    pub(crate) fn try_new_dispute(
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<Amount>,
+    ) -> Result<Self> {
+        Ok(Self {
+            amount: amount.map(try_convert_u64_to_i64).transpose()?,
             client,
             kind: SpecialTransactionKind::Dispute,
             tx,
-        }
+        })
     }
 
-    /// Constructs a new resolve transaction.
-    pub(crate) fn new_resolve(client: ClientId, tx: TransactionId) -> Self {
-        Self {
+    /// Attempts to construct a new resolve transaction.
+    pub(crate) fn try_new_resolve(
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<Amount>,
+    ) -> Result<Self> {
+        Ok(Self {
+            amount: amount.map(try_convert_u64_to_i64).transpose()?,
             client,
             kind: SpecialTransactionKind::Resolve,
             tx,
+        })
+    }
+
+    /// Attempts to construct a new chargeback transaction.
+    pub(crate) fn try_new_chargeback(
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<Amount>,
+    ) -> Result<Self> {
+        Ok(Self {
+            amount: amount.map(try_convert_u64_to_i64).transpose()?,
+            client,
+            kind: SpecialTransactionKind::Chargeback,
+            tx,
+        })
+    }
+
+    /// Returns the part of the referenced transaction's amount targeted by this transaction,
+    /// given the maximum part that can be targeted.
+    ///
+    /// Returns an `Error` if an amount was specified and it's zero or greater than `max`.
+    pub(crate) fn try_get_amount(&self, max: Balance) -> Result<Balance> {
+        match self.amount {
+            None => Ok(max),
+            Some(amount) if amount > 0 && amount <= max => Ok(amount),
+            Some(_) => Err(TransactionError::InvalidDisputeAmount),
         }
     }
+}
+
+impl Settlement {
+    /// Attempts to construct a new capture transaction.
+    pub(crate) fn try_new_capture(
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<Amount>,
+    ) -> Result<Self> {
+        Ok(Self {
+            amount: amount.map(try_convert_u64_to_i64).transpose()?,
+            client,
+            kind: SettlementKind::Capture,
+            tx,
+        })
+    }
 
-    /// Constructs a new chargeback transaction.
-    pub(crate) fn new_chargeback(client: ClientId, tx: TransactionId) -> Self {
+    /// Constructs a new void transaction.
+    pub(crate) fn new_void(client: ClientId, tx: TransactionId) -> Self {
         Self {
+            amount: None,
             client,
-            kind: SpecialTransactionKind::Chargeback,
+            kind: SettlementKind::Void,
             tx,
         }
     }
+
+    /// Returns the captured part of the authorized amount `max`.
+    ///
+    /// Returns an `Error` if an amount was specified and it's zero or greater than `max`.
+    pub(crate) fn try_get_amount(&self, max: Balance) -> Result<Balance> {
+        match self.amount {
+            None => Ok(max),
+            Some(amount) if amount > 0 && amount <= max => Ok(amount),
+            Some(_) => Err(TransactionError::InvalidCaptureAmount),
+        }
+    }
 }
 
 /// Attempts to convert a `u64` to an `i64`.
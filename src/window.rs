@@ -0,0 +1,135 @@
+use crate::TransactionId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/******************************************
+ *               PUBLIC API               *
+ ******************************************/
+
+/// A dispute window, after which a transaction expires and can't be disputed anymore.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeWindow {
+    /// A transaction expires once this many newer regular transactions have been accepted.
+    Transactions(u64),
+    /// A transaction expires once this much wall-clock time has passed since it was accepted.
+    Duration(Duration),
+}
+
+/*******************************************
+ *               PRIVATE API               *
+ *******************************************/
+
+/// The state required to expire transactions in the order they were accepted.
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct Expiry {
+    /// The number of regular transactions accepted so far.
+    accepted: u64,
+    /// The transactions that haven't expired yet, from oldest to newest.
+    pending: VecDeque<PendingTransaction>,
+    /// The IDs of all expired transactions.
+    expired: IdSet,
+}
+
+#[derive(Deserialize, Serialize)]
+struct PendingTransaction {
+    tx: TransactionId,
+    sequence: u64,
+    time: SystemTime,
+}
+
+impl Expiry {
+    /// Starts tracking a newly accepted transaction.
+    pub(crate) fn push(&mut self, tx: TransactionId) {
+        self.pending.push_back(PendingTransaction {
+            tx,
+            sequence: self.accepted,
+            time: SystemTime::now(),
+        });
+        self.accepted += 1;
+    }
+
+    /// Returns a checkpoint of the transactions tracked so far, to roll back to later.
+    pub(crate) fn checkpoint(&self) -> u64 {
+        self.accepted
+    }
+
+    /// Stops tracking the transactions pushed since the given checkpoint.
+    ///
+    /// No transaction must have expired since then (i.e. `pop_expired` wasn't called).
+    pub(crate) fn rollback(&mut self, checkpoint: u64) {
+        let pushed = (self.accepted - checkpoint) as usize;
+        self.pending.truncate(self.pending.len() - pushed);
+        self.accepted = checkpoint;
+    }
+
+    /// Removes the oldest transaction if it's outside the given window, and returns its ID.
+    pub(crate) fn pop_expired(&mut self, window: DisputeWindow) -> Option<TransactionId> {
+        let oldest = self.pending.front()?;
+        let expired = match window {
+            DisputeWindow::Transactions(count) => self.accepted - oldest.sequence > count,
+            DisputeWindow::Duration(duration) => match oldest.time.elapsed() {
+                Ok(elapsed) => elapsed >= duration,
+                // The system clock went backwards: consider that no time has passed.
+                Err(_) => false,
+            },
+        };
+        if !expired {
+            return None;
+        }
+        let tx = self.pending.pop_front()?.tx;
+        self.expired.insert(tx);
+        Some(tx)
+    }
+
+    /// Returns whether the transaction with the given ID has expired.
+    pub(crate) fn is_expired(&self, tx: TransactionId) -> bool {
+        self.expired.contains(tx)
+    }
+}
+
+/// A set of transaction IDs, stored as a map of disjoint ranges (first ID -> last ID).
+///
+/// Transaction IDs are expected to be mostly increasing, so expired transactions should form
+/// a small number of contiguous ranges. In that case, this set only takes a few bytes instead
+/// of the ~16 bytes per transaction that would be needed to keep them in the transactions map.
+#[derive(Default, Deserialize, Serialize)]
+struct IdSet {
+    ranges: BTreeMap<TransactionId, TransactionId>,
+}
+
+impl IdSet {
+    /// Returns the range (first ID, last ID) that starts at or before the given ID, if any.
+    fn range_before(&self, tx: TransactionId) -> Option<(TransactionId, TransactionId)> {
+        let (&first, &last) = self.ranges.range(..=tx).next_back()?;
+        Some((first, last))
+    }
+
+    fn contains(&self, tx: TransactionId) -> bool {
+        matches!(self.range_before(tx), Some((_, last)) if tx <= last)
+    }
+
+    fn insert(&mut self, tx: TransactionId) {
+        if self.contains(tx) {
+            return;
+        }
+
+        // Merge with the range that ends right before this ID, if any.
+        let mut first = tx;
+        if let Some((previous_first, previous_last)) = self.range_before(tx) {
+            if previous_last.checked_add(1) == Some(tx) {
+                first = previous_first;
+            }
+        }
+
+        // Merge with the range that starts right after this ID, if any.
+        let mut last = tx;
+        if let Some(next_first) = tx.checked_add(1) {
+            if let Some(next_last) = self.ranges.remove(&next_first) {
+                last = next_last;
+            }
+        }
+
+        self.ranges.insert(first, last);
+    }
+}
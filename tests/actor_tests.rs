@@ -0,0 +1,93 @@
+use transaction_engine::{ClientId, Currency, EngineActor, Operation, SubmitError};
+use transaction_engine::{TransactionEngine, TransactionError};
+
+fn deposit(client: ClientId, tx: u32, amount: u64) -> Operation {
+    let currency = Currency::default();
+    Operation::Deposit {
+        client,
+        tx,
+        amount,
+        currency,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn engine_actor() {
+    // A tiny queue, so that most submissions wait for room in it.
+    let actor = EngineActor::spawn(TransactionEngine::init(), 2);
+    let tasks = (0..8)
+        .map(|client: ClientId| {
+            let handle = actor.handle();
+            tokio::spawn(async move {
+                for i in 0..100 {
+                    let tx = u32::from(client) * 1000 + i;
+                    handle.submit(deposit(client, tx, 1)).await.unwrap();
+                }
+                let currency = Currency::default();
+                let tx = u32::from(client) * 1000 + 999;
+                let amount = 101;
+                let withdrawal = Operation::Withdrawal {
+                    client,
+                    tx,
+                    amount,
+                    currency,
+                };
+                let error = TransactionError::InsufficientAvailableFunds;
+                assert_eq!(Err(error.into()), handle.submit(withdrawal).await);
+                let dispute = Operation::Dispute {
+                    client,
+                    tx: 999_999,
+                    amount: None,
+                };
+                let error = TransactionError::UnknownTransactionId;
+                assert_eq!(Err(error.into()), handle.submit(dispute).await);
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let engine = actor.shutdown().await;
+    assert_eq!(8, engine.iter_accounts().count());
+    for (_, account) in engine.iter_accounts() {
+        assert_eq!(100, account.get_available_balance());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn engine_actor_shutdown() {
+    let actor = EngineActor::spawn(TransactionEngine::init(), 4);
+    let tasks = (0..8)
+        .map(|client: ClientId| {
+            let handle = actor.handle();
+            tokio::spawn(async move {
+                let mut accepted = 0;
+                for i in 0..1000 {
+                    let tx = u32::from(client) * 1000 + i;
+                    match handle.submit(deposit(client, tx, 1)).await {
+                        Ok(()) => accepted += 1,
+                        Err(error) => {
+                            assert_eq!(SubmitError::ShutDown, error);
+                            break;
+                        }
+                    }
+                }
+                (client, accepted)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Shut down while the tasks are still submitting operations.
+    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    let engine = actor.shutdown().await;
+
+    // Every accepted operation was processed before the engine was returned, and no other one.
+    for task in tasks {
+        let (client, accepted) = task.await.unwrap();
+        let balance = engine
+            .get_account(client)
+            .map_or(0, |a| a.get_available_balance());
+        assert_eq!(accepted, balance);
+    }
+}
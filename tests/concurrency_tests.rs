@@ -0,0 +1,375 @@
+use std::sync::Arc;
+use transaction_engine::{Client, ClientId, ConcurrentEngine, CrossClientDisputePolicy, Currency};
+use transaction_engine::{Event, NegativeBalanceDisputePolicy, Operation, ShardedEngine};
+use transaction_engine::{Receipt, TransactionEngine, TransactionKind};
+
+/// Returns a deterministic pseudo-random sequence of operations on a few clients and
+/// transaction IDs, so that transfers, disputes, and reused IDs often cross shards.
+fn operations(count: usize) -> Vec<Operation> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move |max: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % max
+    };
+    let currency = Currency::default();
+    (0..count)
+        .map(|_| {
+            let client = next(8) as ClientId;
+            let tx = next(200) as u32;
+            let amount = next(100);
+            let partial = if next(4) == 0 { Some(amount) } else { None };
+            match next(12) {
+                0..=2 => Operation::Deposit {
+                    client,
+                    tx,
+                    amount,
+                    currency,
+                },
+                3 => Operation::Withdrawal {
+                    client,
+                    tx,
+                    amount,
+                    currency,
+                },
+                4 => Operation::Transfer {
+                    client,
+                    tx,
+                    to: next(8) as ClientId,
+                    amount,
+                    currency,
+                },
+                5 => Operation::Authorize {
+                    client,
+                    tx,
+                    amount,
+                    currency,
+                },
+                6 => Operation::Capture {
+                    client,
+                    tx,
+                    amount: partial,
+                },
+                7 => Operation::Dispute {
+                    client,
+                    tx,
+                    amount: partial,
+                },
+                8 => Operation::Resolve {
+                    client,
+                    tx,
+                    amount: partial,
+                },
+                9 => Operation::Chargeback {
+                    client,
+                    tx,
+                    amount: partial,
+                },
+                10 => Operation::Unlock { client },
+                _ => Operation::Void { client, tx },
+            }
+        })
+        .collect()
+}
+
+/// Returns the balances and status of a client account, to compare accounts of different engines.
+fn summary(id: ClientId, account: &Client) -> String {
+    format!(
+        "{}: {} {} {} {:?}",
+        id,
+        account.get_available_balance(),
+        account.get_held_balance(),
+        account.get_total_balance(),
+        account.get_status()
+    )
+}
+
+#[test]
+fn sharded_engine() {
+    let policies = [
+        CrossClientDisputePolicy::Reject,
+        CrossClientDisputePolicy::ChargeDisputingClient,
+        CrossClientDisputePolicy::ChargeOriginalClient,
+    ];
+    for policy in policies {
+        let mut expected = TransactionEngine::init();
+        expected.set_cross_client_dispute_policy(policy);
+        let mut sharded = ShardedEngine::init(3);
+        sharded.set_cross_client_dispute_policy(policy);
+
+        // Every other operation is submitted without waiting for its result.
+        for (i, operation) in operations(5_000).into_iter().enumerate() {
+            let error = expected.apply(operation).get_error();
+            if i % 2 == 0 {
+                assert_eq!(error, sharded.process(operation).err(), "{:?}", operation);
+            } else {
+                sharded.submit(operation);
+            }
+        }
+
+        let mut accounts = expected.iter_accounts().collect::<Vec<_>>();
+        accounts.sort_by_key(|&(id, _)| id);
+        let expected = accounts
+            .into_iter()
+            .map(|(id, account)| summary(id, account));
+        let accounts = sharded.collect_accounts();
+        let actual = accounts.iter().map(|(id, account)| summary(*id, account));
+        assert_eq!(expected.collect::<Vec<_>>(), actual.collect::<Vec<_>>());
+        let account = sharded.get_account(5).unwrap();
+        assert_eq!(summary(5, &account), summary(5, &accounts[5].1));
+    }
+}
+
+/// Returns the operation with its client and transaction IDs remapped to those of one thread,
+/// so that the operations of different threads never involve the same accounts or transactions
+/// (but their transactions still share the same locks).
+fn remap(operation: Operation, thread: u32, threads: u32) -> Operation {
+    let client = |id: ClientId| id * threads as ClientId + thread as ClientId;
+    let tx = |id: u32| id * threads + thread;
+    match operation {
+        Operation::Deposit {
+            client: c,
+            tx: t,
+            amount,
+            currency,
+        } => Operation::Deposit {
+            client: client(c),
+            tx: tx(t),
+            amount,
+            currency,
+        },
+        Operation::Withdrawal {
+            client: c,
+            tx: t,
+            amount,
+            currency,
+        } => Operation::Withdrawal {
+            client: client(c),
+            tx: tx(t),
+            amount,
+            currency,
+        },
+        Operation::Transfer {
+            client: c,
+            tx: t,
+            to,
+            amount,
+            currency,
+        } => Operation::Transfer {
+            client: client(c),
+            tx: tx(t),
+            to: client(to),
+            amount,
+            currency,
+        },
+        Operation::Authorize {
+            client: c,
+            tx: t,
+            amount,
+            currency,
+        } => Operation::Authorize {
+            client: client(c),
+            tx: tx(t),
+            amount,
+            currency,
+        },
+        Operation::Capture {
+            client: c,
+            tx: t,
+            amount,
+        } => Operation::Capture {
+            client: client(c),
+            tx: tx(t),
+            amount,
+        },
+        Operation::Void { client: c, tx: t } => Operation::Void {
+            client: client(c),
+            tx: tx(t),
+        },
+        Operation::Dispute {
+            client: c,
+            tx: t,
+            amount,
+        } => Operation::Dispute {
+            client: client(c),
+            tx: tx(t),
+            amount,
+        },
+        Operation::Resolve {
+            client: c,
+            tx: t,
+            amount,
+        } => Operation::Resolve {
+            client: client(c),
+            tx: tx(t),
+            amount,
+        },
+        Operation::Chargeback {
+            client: c,
+            tx: t,
+            amount,
+        } => Operation::Chargeback {
+            client: client(c),
+            tx: tx(t),
+            amount,
+        },
+        Operation::Unlock { client: c } => Operation::Unlock { client: client(c) },
+        operation => operation,
+    }
+}
+
+#[test]
+fn concurrent_engine() {
+    const THREADS: u32 = 4;
+    let policies = [
+        CrossClientDisputePolicy::Reject,
+        CrossClientDisputePolicy::ChargeDisputingClient,
+        CrossClientDisputePolicy::ChargeOriginalClient,
+    ];
+    for policy in policies {
+        // Each thread processes its own sequence of operations against the shared engine.
+        let mut engine = ConcurrentEngine::init();
+        engine.set_cross_client_dispute_policy(policy);
+        let engine = Arc::new(engine);
+        let threads = (0..THREADS)
+            .map(|thread| {
+                let engine = engine.clone();
+                std::thread::spawn(move || {
+                    let operations = operations(5_000).into_iter();
+                    let operations = operations.map(|op| remap(op, thread, THREADS));
+                    let results = operations.map(|op| (op, engine.apply(op)));
+                    results.collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = threads.into_iter().map(|thread| thread.join().unwrap());
+
+        // The same operations processed one thread after the other must give the same outcomes.
+        let mut expected = TransactionEngine::init();
+        expected.set_cross_client_dispute_policy(policy);
+        for (operation, event) in results.flatten() {
+            assert_eq!(expected.apply(operation), event, "{:?}", operation);
+        }
+
+        let mut accounts = expected.iter_accounts().collect::<Vec<_>>();
+        accounts.sort_by_key(|&(id, _)| id);
+        let expected = accounts
+            .into_iter()
+            .map(|(id, account)| summary(id, account));
+        let accounts = engine.collect_accounts();
+        let actual = accounts.iter().map(|(id, account)| summary(*id, account));
+        assert_eq!(expected.collect::<Vec<_>>(), actual.collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn concurrent_transfers() {
+    const THREADS: u32 = 4;
+    const CLIENTS: u32 = 16;
+    let engine = Arc::new(ConcurrentEngine::init());
+    for client in 0..CLIENTS {
+        engine
+            .deposit(client as ClientId, client, 1000_0000)
+            .unwrap();
+    }
+
+    // Every thread transfers funds back and forth between the same accounts, in both directions.
+    let threads = (0..THREADS)
+        .map(|thread| {
+            let engine = engine.clone();
+            std::thread::spawn(move || {
+                for i in 0..10_000 {
+                    let from = (i + thread) % CLIENTS;
+                    let to = (i * 7 + thread * 3 + 1) % CLIENTS;
+                    let tx = CLIENTS + i * THREADS + thread;
+                    let amount = u64::from(i % 100) * 1_0000;
+                    let _ = engine.transfer(from as ClientId, to as ClientId, tx, amount);
+                    if i % 10 == 0 {
+                        let _ = engine.dispute(from as ClientId, tx);
+                        let _ = engine.resolve(from as ClientId, tx);
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    threads
+        .into_iter()
+        .for_each(|thread| thread.join().unwrap());
+
+    // No funds were created or lost, and no account was left with a negative or held balance.
+    let accounts = engine.collect_accounts();
+    assert_eq!(CLIENTS as usize, accounts.len());
+    let total = accounts.iter().map(|(_, account)| {
+        assert!(account.get_available_balance() >= 0);
+        assert_eq!(0, account.get_held_balance());
+        account.get_total_balance()
+    });
+    assert_eq!(i64::from(CLIENTS) * 1000_0000, total.sum::<i64>());
+}
+
+#[test]
+fn concurrent_shared_accounts() {
+    const THREADS: usize = 4;
+    const OPERATIONS: usize = 5_000;
+    let mut engine = ConcurrentEngine::init();
+    engine.set_negative_balance_dispute_policy(NegativeBalanceDisputePolicy::Reject);
+    let engine = Arc::new(engine);
+
+    // Every thread processes a different sequence of operations, but on the same few clients
+    // and transaction IDs, so that they constantly contend for the same locks.
+    let operations = Arc::new(operations(THREADS * OPERATIONS));
+    let threads = (0..THREADS)
+        .map(|thread| {
+            let engine = engine.clone();
+            let operations = operations.clone();
+            std::thread::spawn(move || {
+                let operations = &operations[thread * OPERATIONS..(thread + 1) * OPERATIONS];
+                let events = operations.iter().map(|&op| engine.apply(op));
+                events.collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+    let events = threads
+        .into_iter()
+        .flat_map(|thread| thread.join().unwrap())
+        .collect::<Vec<_>>();
+    assert!(events.iter().any(Event::is_accepted));
+
+    // No funds were created or lost: the total balance of all accounts is the sum of the
+    // amounts credited and debited by the accepted transactions, whatever their order was.
+    // A chargeback reverses a deposit, or refunds a withdrawal or a captured authorization
+    // (a transfer is reversed between two accounts).
+    let amount = |receipt: &Receipt| receipt.amount as i64;
+    let mut expected = 0;
+    for event in &events {
+        expected += match event {
+            Event::DepositAccepted(receipt) => amount(receipt),
+            Event::WithdrawalAccepted(receipt) => -amount(receipt),
+            Event::AuthorizationCaptured(receipt) => -amount(receipt),
+            Event::AccountLocked(receipt) => {
+                let transaction = engine.get_transaction(receipt.tx).unwrap();
+                match transaction.get_kind() {
+                    TransactionKind::Deposit => -amount(receipt),
+                    TransactionKind::Transfer => 0,
+                    _ => amount(receipt),
+                }
+            }
+            _ => 0,
+        };
+    }
+
+    // No account was ever left with a negative available or held balance.
+    for event in &events {
+        if let Event::FundsHeld(receipt) | Event::AccountLocked(receipt) = event {
+            assert!(receipt.available >= 0 && receipt.held >= 0, "{:?}", event);
+        }
+    }
+    let accounts = engine.collect_accounts();
+    let total = accounts.iter().map(|(_, account)| {
+        assert!(account.get_available_balance() >= 0);
+        assert!(account.get_held_balance() >= 0);
+        account.get_total_balance()
+    });
+    assert_eq!(expected, total.sum::<i64>());
+}
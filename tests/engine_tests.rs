@@ -0,0 +1,1181 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use transaction_engine::CrossClientDisputePolicy;
+use transaction_engine::{AccountStatus, Storage, TransactionEngine, TransactionError::*};
+use transaction_engine::{Client, ClientId, Event, Operation, OperationKind, StandardFeePolicy};
+use transaction_engine::{CreditLimit, Currency, DisputeWindow, TransactionKind, TransactionState};
+use transaction_engine::{HashMapStorage, Transaction, TransactionIter};
+use transaction_engine::{NegativeBalanceDisputePolicy, VecStorage, WithdrawalDisputePolicy};
+
+#[test]
+fn unlock_after_chargeback() {
+    let mut engine = TransactionEngine::init();
+    engine.deposit(1, 1, 100).unwrap();
+    engine.deposit(1, 2, 100).unwrap();
+    engine.dispute(1, 1).unwrap();
+    engine.chargeback(1, 1).unwrap();
+    assert_eq!(Err(ClientAccountLocked), engine.deposit(1, 3, 100));
+
+    engine.unlock(1).unwrap();
+    assert_eq!(Err(ClientAccountNotLocked), engine.unlock(1));
+    assert_eq!(Err(TransactionAlreadyReversed), engine.dispute(1, 1));
+    engine.deposit(1, 3, 100).unwrap();
+
+    let client = engine.get_account(1).unwrap();
+    assert_eq!(AccountStatus::Active, client.get_status());
+    assert_eq!(200, client.get_total_balance());
+}
+
+#[test]
+fn freeze_and_unfreeze() {
+    let mut engine = TransactionEngine::init();
+    assert_eq!(Err(UnknownClientId), engine.freeze(1));
+    engine.deposit(1, 1, 100).unwrap();
+
+    engine.freeze(1).unwrap();
+    assert_eq!(Err(ClientAccountAlreadyFrozen), engine.freeze(1));
+    assert_eq!(Err(ClientAccountFrozen), engine.withdrawal(1, 2, 50));
+    assert_eq!(Err(ClientAccountFrozen), engine.dispute(1, 1));
+    assert!(engine.get_account(1).unwrap().is_frozen());
+
+    engine.unfreeze(1).unwrap();
+    assert_eq!(Err(ClientAccountNotFrozen), engine.unfreeze(1));
+    engine.withdrawal(1, 2, 50).unwrap();
+    assert_eq!(50, engine.get_account(1).unwrap().get_total_balance());
+}
+
+#[test]
+fn close() {
+    let mut engine = TransactionEngine::init();
+    engine.deposit(1, 1, 100).unwrap();
+    assert_eq!(Err(ClientAccountNotEmpty), engine.close(1));
+
+    engine.withdrawal(1, 2, 100).unwrap();
+    engine.close(1).unwrap();
+    assert_eq!(Err(ClientAccountClosed), engine.close(1));
+    assert_eq!(Err(ClientAccountClosed), engine.unlock(1));
+    assert_eq!(Err(ClientAccountClosed), engine.freeze(1));
+    assert_eq!(Err(ClientAccountClosed), engine.deposit(1, 3, 100));
+    assert!(engine.get_account(1).unwrap().is_closed());
+
+    // An account with a shortfall isn't empty, even if its balances are zero.
+    engine.set_negative_balance_dispute_policy(NegativeBalanceDisputePolicy::CapHold);
+    engine.deposit(2, 4, 100).unwrap();
+    engine.withdrawal(2, 5, 100).unwrap();
+    engine.dispute(2, 4).unwrap();
+    assert_eq!(Err(ClientAccountNotEmpty), engine.close(2));
+}
+
+#[test]
+fn query_transactions() {
+    let mut engine = TransactionEngine::init();
+    engine.deposit(1, 1, 100).unwrap();
+    engine.deposit(2, 2, 200).unwrap();
+    engine.withdrawal(1, 3, 50).unwrap();
+    engine.withdrawal(1, 4, 500).unwrap_err();
+    engine.dispute(1, 3).unwrap();
+
+    let transaction = engine.get_transaction(3).unwrap().unwrap();
+    assert_eq!(1, transaction.get_client());
+    assert_eq!(50, transaction.get_amount());
+    assert_eq!(TransactionKind::Withdrawal, transaction.get_kind());
+    assert_eq!(TransactionState::Disputed, transaction.get_state());
+    assert!(engine.get_transaction(4).unwrap().is_none());
+
+    let mut ids = engine
+        .iter_client_transactions(1)
+        .map(|t| t.unwrap().get_id())
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    assert_eq!(vec![1, 3], ids);
+
+    let accepted = Some(TransactionState::Accepted);
+    let deposits = Some(TransactionKind::Deposit);
+    assert_eq!(
+        1,
+        engine.filter_client_transactions(1, None, accepted).count()
+    );
+    assert_eq!(
+        1,
+        engine.filter_client_transactions(2, deposits, None).count()
+    );
+    assert_eq!(0, engine.filter_client_transactions(3, None, None).count());
+}
+
+#[test]
+fn dispute_window_transactions() {
+    let mut engine = TransactionEngine::init();
+    engine.set_dispute_window(Some(DisputeWindow::Transactions(2)));
+    engine.deposit(1, 1, 100).unwrap();
+    engine.deposit(1, 2, 100).unwrap();
+    engine.dispute(1, 2).unwrap();
+    engine.deposit(1, 3, 100).unwrap();
+
+    // 2 newer transactions were accepted after the 1st one, so it expired.
+    assert_eq!(Err(DisputeWindowExpired), engine.dispute(1, 1));
+    assert_eq!(
+        Err(TransactionIdAlreadyProcessed),
+        engine.deposit(1, 1, 100)
+    );
+    assert_eq!(Err(UnknownTransactionId), engine.dispute(1, 4));
+    assert!(engine.get_transaction(1).unwrap().is_none());
+
+    // The 2nd transaction expires while disputed, so it can still be resolved once.
+    engine.deposit(1, 4, 100).unwrap();
+    assert!(engine.get_transaction(2).unwrap().is_some());
+    engine.resolve(1, 2).unwrap();
+    assert!(engine.get_transaction(2).unwrap().is_none());
+    assert_eq!(Err(DisputeWindowExpired), engine.dispute(1, 2));
+
+    let client = engine.get_account(1).unwrap();
+    assert_eq!(400, client.get_available_balance());
+    assert_eq!(400, client.get_total_balance());
+}
+
+#[test]
+fn dispute_window_duration() {
+    let mut engine = TransactionEngine::init();
+    engine.set_dispute_window(Some(DisputeWindow::Duration(Duration::ZERO)));
+    engine.deposit(1, 1, 100).unwrap();
+    assert_eq!(Err(DisputeWindowExpired), engine.dispute(1, 1));
+    assert_eq!(0, engine.iter_transactions().count());
+}
+
+#[test]
+fn partial_disputes() {
+    let mut engine = TransactionEngine::init();
+    engine.deposit(1, 1, 100).unwrap();
+    assert_eq!(Err(InvalidDisputeAmount), engine.dispute_partial(1, 1, 0));
+    assert_eq!(Err(InvalidDisputeAmount), engine.dispute_partial(1, 1, 101));
+    assert_eq!(
+        Err(TransactionNotDisputed),
+        engine.resolve_partial(1, 1, 10)
+    );
+
+    engine.dispute_partial(1, 1, 60).unwrap();
+    engine.dispute_partial(1, 1, 30).unwrap();
+    assert_eq!(Err(InvalidDisputeAmount), engine.dispute_partial(1, 1, 20));
+    assert_eq!(Err(InvalidDisputeAmount), engine.resolve_partial(1, 1, 91));
+    engine.resolve_partial(1, 1, 40).unwrap();
+
+    let client = engine.get_account(1).unwrap();
+    assert_eq!(50, client.get_available_balance());
+    assert_eq!(50, client.get_held_balance());
+
+    // Dispute the remaining 50, then charge back part of what's held.
+    engine.dispute(1, 1).unwrap();
+    assert_eq!(Err(TransactionAlreadyDisputed), engine.dispute(1, 1));
+    engine.chargeback_partial(1, 1, 20).unwrap();
+    let transaction = engine.get_transaction(1).unwrap().unwrap();
+    assert_eq!(TransactionState::Disputed, transaction.get_state());
+    assert_eq!(80, transaction.get_disputed_amount());
+    assert_eq!(20, transaction.get_reversed_amount());
+
+    engine.unlock(1).unwrap();
+    engine.resolve_partial(1, 1, 30).unwrap();
+    engine.chargeback(1, 1).unwrap();
+    assert_eq!(
+        TransactionState::Accepted,
+        engine.get_transaction(1).unwrap().unwrap().get_state()
+    );
+    assert_eq!(30, engine.get_account(1).unwrap().get_total_balance());
+
+    // The transaction is only reversed once its whole amount was charged back.
+    engine.unlock(1).unwrap();
+    engine.dispute(1, 1).unwrap();
+    engine.chargeback(1, 1).unwrap();
+    assert_eq!(
+        TransactionState::Reversed,
+        engine.get_transaction(1).unwrap().unwrap().get_state()
+    );
+    engine.unlock(1).unwrap();
+    assert_eq!(Err(TransactionAlreadyReversed), engine.dispute(1, 1));
+
+    let client = engine.get_account(1).unwrap();
+    assert_eq!(0, client.get_available_balance());
+    assert_eq!(0, client.get_held_balance());
+    assert_eq!(0, client.get_total_balance());
+}
+
+#[test]
+fn multi_currency() {
+    let usd: Currency = "USD".parse().unwrap();
+    let btc: Currency = "BTC".parse().unwrap();
+    assert!("TOO-LONG-CODE".parse::<Currency>().is_err());
+
+    let mut engine = TransactionEngine::init();
+    engine.deposit_in(1, 1, u64::MAX >> 1, usd).unwrap();
+    engine.deposit_in(1, 2, 100, btc).unwrap();
+    assert_eq!(Err(BalanceWouldOverflow), engine.deposit_in(1, 3, 1, usd));
+    assert_eq!(Err(InsufficientAvailableFunds), engine.withdrawal(1, 4, 1));
+    engine.withdrawal_in(1, 5, 40, btc).unwrap();
+
+    // The dispute applies to the currency of the referenced transaction.
+    engine.dispute(1, 2).unwrap();
+    assert_eq!(
+        btc,
+        engine.get_transaction(2).unwrap().unwrap().get_currency()
+    );
+    let client = engine.get_account(1).unwrap();
+    let wallet = client.get_wallet(btc).unwrap();
+    assert_eq!(-40, wallet.get_available_balance());
+    assert_eq!(100, wallet.get_held_balance());
+    assert_eq!(0, client.get_wallet(usd).unwrap().get_held_balance());
+    assert!(client.get_wallet(Currency::default()).is_none());
+    assert_eq!(
+        vec![btc, usd],
+        client.iter_wallets().map(|(c, _)| c).collect::<Vec<_>>()
+    );
+
+    // An account can only be closed once all of its wallets are empty.
+    engine.chargeback(1, 2).unwrap();
+    engine.unlock(1).unwrap();
+    engine.deposit_in(1, 6, 40, btc).unwrap();
+    assert_eq!(
+        0,
+        engine
+            .get_account(1)
+            .unwrap()
+            .get_wallet(btc)
+            .unwrap()
+            .get_total_balance()
+    );
+    assert_eq!(Err(ClientAccountNotEmpty), engine.close(1));
+    engine.withdrawal_in(1, 7, u64::MAX >> 1, usd).unwrap();
+    engine.close(1).unwrap();
+}
+
+#[test]
+fn transfer() {
+    let mut engine = TransactionEngine::init();
+    assert_eq!(Err(InvalidFirstTransaction), engine.transfer(1, 2, 1, 100));
+    engine.deposit(1, 1, 100).unwrap();
+    engine.deposit(2, 2, i64::MAX as u64).unwrap();
+
+    // A failed transfer leaves both accounts untouched.
+    assert_eq!(Err(BalanceWouldOverflow), engine.transfer(1, 2, 3, 50));
+    assert_eq!(100, engine.get_account(1).unwrap().get_total_balance());
+    engine.withdrawal(2, 4, 100).unwrap();
+    engine.freeze(2).unwrap();
+    assert_eq!(Err(ClientAccountFrozen), engine.transfer(1, 2, 5, 50));
+    assert_eq!(100, engine.get_account(1).unwrap().get_total_balance());
+    engine.unfreeze(2).unwrap();
+
+    engine.transfer(1, 2, 6, 50).unwrap();
+    let transaction = engine.get_transaction(6).unwrap().unwrap();
+    assert_eq!(TransactionKind::Transfer, transaction.get_kind());
+    assert_eq!(Some(2), transaction.get_recipient());
+
+    // Only the sender can dispute a transfer. The funds are held in the recipient's account.
+    assert_eq!(Err(WrongClientId), engine.dispute(2, 6));
+    engine.dispute(1, 6).unwrap();
+    assert_eq!(50, engine.get_account(2).unwrap().get_held_balance());
+    assert_eq!(0, engine.get_account(1).unwrap().get_held_balance());
+
+    // A chargeback moves the funds back to the sender's account and locks it.
+    engine.chargeback(1, 6).unwrap();
+    let sender = engine.get_account(1).unwrap();
+    assert_eq!(100, sender.get_total_balance());
+    assert!(sender.is_locked());
+    let recipient = engine.get_account(2).unwrap();
+    assert_eq!(i64::MAX - 100, recipient.get_total_balance());
+    assert_eq!(0, recipient.get_held_balance());
+    assert!(!recipient.is_locked());
+}
+
+#[test]
+fn authorization() {
+    let mut engine = TransactionEngine::init();
+    assert_eq!(Err(InvalidFirstTransaction), engine.authorize(1, 1, 100));
+    engine.deposit(1, 1, 100).unwrap();
+    assert_eq!(Err(TransactionNotAnAuthorization), engine.capture(1, 1));
+    assert_eq!(Err(InsufficientAvailableFunds), engine.authorize(1, 2, 101));
+
+    engine.authorize(1, 2, 60).unwrap();
+    let client = engine.get_account(1).unwrap();
+    assert_eq!(40, client.get_available_balance());
+    assert_eq!(60, client.get_held_balance());
+    assert_eq!(Err(AuthorizationNotCaptured), engine.dispute(1, 2));
+    assert_eq!(Err(WrongClientId), engine.void(2, 2));
+    assert_eq!(Err(InvalidCaptureAmount), engine.capture_partial(1, 2, 61));
+
+    // A partial capture debits the captured amount and releases the rest.
+    engine.capture_partial(1, 2, 25).unwrap();
+    assert_eq!(Err(AuthorizationAlreadyCaptured), engine.capture(1, 2));
+    let transaction = engine.get_transaction(2).unwrap().unwrap();
+    assert_eq!(TransactionState::Accepted, transaction.get_state());
+    assert_eq!(25, transaction.get_amount());
+    let client = engine.get_account(1).unwrap();
+    assert_eq!(75, client.get_available_balance());
+    assert_eq!(75, client.get_total_balance());
+
+    // A void releases the whole amount.
+    engine.authorize(1, 3, 75).unwrap();
+    engine.void(1, 3).unwrap();
+    assert_eq!(Err(AuthorizationAlreadyVoided), engine.void(1, 3));
+    assert_eq!(Err(AuthorizationAlreadyVoided), engine.dispute(1, 3));
+    assert_eq!(
+        TransactionState::Voided,
+        engine.get_transaction(3).unwrap().unwrap().get_state()
+    );
+    assert_eq!(75, engine.get_account(1).unwrap().get_available_balance());
+
+    // A captured authorization can be disputed and charged back like a withdrawal.
+    engine.dispute(1, 2).unwrap();
+    engine.chargeback(1, 2).unwrap();
+    assert_eq!(100, engine.get_account(1).unwrap().get_total_balance());
+}
+
+#[test]
+fn authorization_outlives_dispute_window() {
+    let mut engine = TransactionEngine::init();
+    engine.set_dispute_window(Some(DisputeWindow::Transactions(1)));
+    engine.deposit(1, 1, 100).unwrap();
+    engine.authorize(1, 2, 50).unwrap();
+    engine.deposit(1, 3, 100).unwrap();
+    engine.deposit(1, 4, 100).unwrap();
+
+    // The authorization expired, but it's kept until it's settled.
+    assert!(engine.get_transaction(1).unwrap().is_none());
+    engine.capture(1, 2).unwrap();
+    assert!(engine.get_transaction(2).unwrap().is_none());
+    assert_eq!(250, engine.get_account(1).unwrap().get_total_balance());
+}
+
+#[test]
+fn credit_limit() {
+    let usd: Currency = "USD".parse().unwrap();
+    let limit = CreditLimit {
+        overdraft: 100,
+        floor: None,
+    };
+
+    let mut engine = TransactionEngine::init();
+    assert_eq!(Err(UnknownClientId), engine.set_credit_limit(1, usd, limit));
+    engine.deposit(1, 1, 50).unwrap();
+    engine.set_credit_limit(1, usd, limit).unwrap();
+    assert_eq!(Err(InsufficientAvailableFunds), engine.withdrawal(1, 2, 60));
+
+    // The overdraft applies to the given currency only.
+    engine.withdrawal_in(1, 3, 60, usd).unwrap();
+    assert_eq!(
+        Err(OverdraftLimitExceeded),
+        engine.withdrawal_in(1, 4, 41, usd)
+    );
+    engine.transfer_in(1, 2, 5, 40, usd).unwrap();
+    let account = engine.get_account(1).unwrap();
+    assert_eq!(
+        -100,
+        account.get_wallet(usd).unwrap().get_available_balance()
+    );
+    assert_eq!(limit, account.get_credit_limit(usd));
+
+    // A credit limit in an unused currency doesn't create a wallet.
+    let eur: Currency = "EUR".parse().unwrap();
+    engine.set_credit_limit(1, eur, limit).unwrap();
+    let account = engine.get_account(1).unwrap();
+    assert!(account.get_wallet(eur).is_none());
+    assert_eq!(2, account.iter_wallets().count());
+    assert_eq!(limit, account.get_credit_limit(eur));
+
+    // The floor applies to the total balance.
+    let limit = CreditLimit {
+        overdraft: 100,
+        floor: Some(-20),
+    };
+    engine
+        .set_credit_limit(1, Currency::default(), limit)
+        .unwrap();
+    engine.withdrawal(1, 6, 60).unwrap();
+    assert_eq!(Err(TotalBalanceBelowFloor), engine.withdrawal(1, 7, 15));
+    engine.withdrawal(1, 8, 10).unwrap();
+    assert_eq!(-20, engine.get_account(1).unwrap().get_total_balance());
+
+    // An authorization can't make the held balance overflow, even within the overdraft.
+    let mut engine = TransactionEngine::init();
+    engine.deposit(1, 1, i64::MAX as u64).unwrap();
+    engine.dispute(1, 1).unwrap();
+    let limit = CreditLimit {
+        overdraft: 1,
+        floor: None,
+    };
+    engine
+        .set_credit_limit(1, Currency::default(), limit)
+        .unwrap();
+    assert_eq!(Err(BalanceWouldOverflow), engine.authorize(1, 2, 1));
+    assert_eq!(i64::MAX, engine.get_account(1).unwrap().get_held_balance());
+}
+
+#[test]
+fn fees() {
+    let mut engine = TransactionEngine::init();
+    engine.set_fee_policy(Some(Box::new(StandardFeePolicy {
+        withdrawal_fixed: 5,
+        withdrawal_rate: 1000, // 10%
+        chargeback_flat: 30,
+    })));
+    engine.set_fee_account(Some(99));
+    let (sender, receiver) = std::sync::mpsc::channel();
+    engine.subscribe(move |event| sender.send(*event).unwrap());
+
+    // A withdrawal is rejected if its amount and fee don't both fit in the available funds.
+    engine.deposit(1, 1, 100).unwrap();
+    engine.deposit(1, 2, 50).unwrap();
+    assert_eq!(
+        Err(InsufficientAvailableFunds),
+        engine.withdrawal(1, 3, 140)
+    );
+    engine.withdrawal(1, 4, 50).unwrap();
+    let account = engine.get_account(1).unwrap();
+    assert_eq!(90, account.get_total_balance());
+    assert_eq!(10, engine.get_account(99).unwrap().get_total_balance());
+
+    // The fee is visible in the receipt of the transaction.
+    let events = receiver.try_iter().collect::<Vec<_>>();
+    match events.last().unwrap() {
+        Event::WithdrawalAccepted(receipt) => {
+            assert_eq!((50, 10, 90), (receipt.amount, receipt.fee, receipt.total))
+        }
+        event => panic!("unexpected event: {:?}", event),
+    }
+
+    // A chargeback fee is charged even if the available balance becomes negative.
+    engine.dispute(1, 1).unwrap();
+    engine.chargeback(1, 1).unwrap();
+    let account = engine.get_account(1).unwrap();
+    assert_eq!(
+        (-40, 0, -40),
+        (
+            account.get_available_balance(),
+            account.get_held_balance(),
+            account.get_total_balance()
+        )
+    );
+    assert_eq!(40, engine.get_account(99).unwrap().get_total_balance());
+
+    // A rejected transaction isn't charged any fee.
+    assert_eq!(Err(ClientAccountLocked), engine.withdrawal(1, 5, 1));
+    assert_eq!(-40, engine.get_account(1).unwrap().get_total_balance());
+    assert_eq!(40, engine.get_account(99).unwrap().get_total_balance());
+}
+
+#[test]
+fn custom_fee_policy() {
+    let mut engine = TransactionEngine::init();
+    let fee = |kind, _, _| match kind {
+        OperationKind::Deposit | OperationKind::Transfer => 1,
+        _ => 0,
+    };
+    engine.set_fee_policy(Some(Box::new(fee)));
+    engine.deposit(1, 1, 10).unwrap();
+    engine.transfer(1, 2, 2, 8).unwrap();
+    assert_eq!(0, engine.get_account(1).unwrap().get_total_balance());
+    assert_eq!(8, engine.get_account(2).unwrap().get_total_balance());
+
+    // Without a fee account, the fees are only debited from the client accounts.
+    assert_eq!(None, engine.get_fee_account());
+    assert_eq!(2, engine.iter_accounts().count());
+
+    // The transfer fails after its fee was charged, so the fee is refunded.
+    engine.freeze(2).unwrap();
+    engine.deposit(1, 3, 10).unwrap();
+    assert_eq!(Err(ClientAccountFrozen), engine.transfer(1, 2, 4, 5));
+    assert_eq!(9, engine.get_account(1).unwrap().get_total_balance());
+
+    // A deposit rejected because of its fee doesn't leave an empty account behind.
+    let fee = |_, _, _| u64::MAX;
+    engine.set_fee_policy(Some(Box::new(fee)));
+    assert_eq!(Err(TransactionAmountTooLarge), engine.deposit(5, 5, 10));
+    assert!(engine.get_account(5).is_none());
+
+    // A dispute rejected after its fee was charged doesn't leave a fee account behind.
+    let fee = |kind, _, _| match kind {
+        OperationKind::Dispute => 1,
+        _ => 0,
+    };
+    engine.set_fee_policy(Some(Box::new(fee)));
+    engine.set_fee_account(Some(0));
+    engine.set_negative_balance_dispute_policy(NegativeBalanceDisputePolicy::Reject);
+    engine.deposit(6, 6, 10).unwrap();
+    engine.withdrawal(6, 7, 5).unwrap();
+    assert_eq!(Err(DisputeExceedsAvailableFunds), engine.dispute(6, 6));
+    assert_eq!(5, engine.get_account(6).unwrap().get_total_balance());
+    assert!(engine.get_account(0).is_none());
+}
+
+#[test]
+fn subscribe() {
+    let currency = Currency::default();
+    let mut engine = TransactionEngine::init();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    engine.subscribe(move |event| sender.send(*event).unwrap());
+
+    // Every accepted or rejected operation emits a single event, in order.
+    engine.deposit(1, 1, 100).unwrap();
+    engine.withdrawal(1, 2, 150).unwrap_err();
+    engine.dispute(1, 1).unwrap();
+    engine.resolve(1, 1).unwrap();
+    engine.freeze(1).unwrap();
+    engine.unfreeze(1).unwrap();
+
+    // A batch emits one event per operation, in order.
+    let operations = [
+        Operation::Withdrawal {
+            client: 1,
+            tx: 3,
+            amount: 30,
+            currency,
+        },
+        Operation::Transfer {
+            client: 1,
+            tx: 4,
+            to: 2,
+            amount: 20,
+            currency,
+        },
+    ];
+    engine.batch(&operations).unwrap();
+
+    // A dry run emits nothing, whether the operation would be accepted or rejected.
+    engine.dry_run(operations[0]).unwrap_err();
+    let deposit = Operation::Deposit {
+        client: 1,
+        tx: 5,
+        amount: 10,
+        currency,
+    };
+    engine.dry_run(deposit).unwrap();
+
+    let events = receiver.try_iter().collect::<Vec<_>>();
+    assert_eq!(8, events.len());
+    assert!(matches!(events[0], Event::DepositAccepted(r) if r.tx == 1 && r.available == 100));
+    assert!(matches!(events[1], Event::Rejected(r) if r.tx == Some(2)));
+    assert_eq!(Some(InsufficientAvailableFunds), events[1].get_error());
+    assert!(matches!(events[2], Event::FundsHeld(r) if r.tx == 1 && r.held == 100));
+    assert!(matches!(events[3], Event::DisputeResolved(r) if r.tx == 1 && r.held == 0));
+    assert!(matches!(events[4], Event::StatusChanged(c) if c.status == AccountStatus::Frozen));
+    assert!(matches!(events[5], Event::StatusChanged(c) if c.status == AccountStatus::Active));
+    assert!(matches!(events[6], Event::WithdrawalAccepted(r) if r.tx == 3 && r.total == 70));
+    assert!(matches!(events[7], Event::TransferAccepted(r) if r.tx == 4 && r.total == 50));
+}
+
+#[test]
+fn batch() {
+    let currency = Currency::default();
+    let mut engine = TransactionEngine::init();
+    engine.set_dispute_window(Some(DisputeWindow::Transactions(10)));
+    engine.deposit(1, 1, 100).unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    engine.subscribe(move |event| sender.send(*event).unwrap());
+
+    // The failed operation is reported, and every previous change is rolled back.
+    let operations = [
+        Operation::Transfer {
+            client: 1,
+            tx: 2,
+            to: 2,
+            amount: 60,
+            currency,
+        },
+        Operation::Dispute {
+            client: 1,
+            tx: 1,
+            amount: Some(10),
+        },
+        Operation::Deposit {
+            client: 3,
+            tx: 3,
+            amount: 10,
+            currency,
+        },
+        Operation::Withdrawal {
+            client: 1,
+            tx: 4,
+            amount: 50,
+            currency,
+        },
+    ];
+    let error = engine.batch(&operations).unwrap_err();
+    assert_eq!(
+        (3, operations[3], InsufficientAvailableFunds),
+        (error.index, error.operation, error.error)
+    );
+    assert_eq!(100, engine.get_account(1).unwrap().get_available_balance());
+    assert_eq!(1, engine.iter_accounts().count());
+    assert!(engine.get_transaction(2).unwrap().is_none());
+    assert_eq!(
+        0,
+        engine
+            .get_transaction(1)
+            .unwrap()
+            .unwrap()
+            .get_disputed_amount()
+    );
+
+    // Only the rejection of the failed operation is emitted.
+    let events = receiver.try_iter().collect::<Vec<_>>();
+    assert_eq!(1, events.len());
+    assert!(matches!(events[0], Event::Rejected(rejection) if rejection.tx == Some(4)));
+
+    // The rolled back transaction IDs can be reused.
+    engine.batch(&operations[..3]).unwrap();
+    assert_eq!(30, engine.get_account(1).unwrap().get_available_balance());
+    assert_eq!(60, engine.get_account(2).unwrap().get_total_balance());
+    assert_eq!(3, receiver.try_iter().count());
+}
+
+#[test]
+fn dry_run() {
+    let currency = Currency::default();
+    let mut engine = TransactionEngine::init();
+    engine.set_fee_policy(Some(Box::new(StandardFeePolicy {
+        withdrawal_fixed: 1,
+        withdrawal_rate: 0,
+        chargeback_flat: 0,
+    })));
+    engine.set_fee_account(Some(99));
+    engine.deposit(1, 1, 100).unwrap();
+
+    // The result is the same as if the operations were processed, but nothing changes.
+    let withdrawal = Operation::Withdrawal {
+        client: 1,
+        tx: 2,
+        amount: 99,
+        currency,
+    };
+    let deposit = Operation::Deposit {
+        client: 2,
+        tx: 3,
+        amount: 10,
+        currency,
+    };
+    assert_eq!(Ok(()), engine.dry_run(withdrawal));
+    assert_eq!(Ok(()), engine.dry_run(deposit));
+    assert_eq!(
+        Err(WrongClientId),
+        engine.dry_run(Operation::Dispute {
+            client: 2,
+            tx: 1,
+            amount: None
+        })
+    );
+    assert_eq!(
+        Err(InsufficientAvailableFunds),
+        engine.dry_run(Operation::Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: 100,
+            currency
+        })
+    );
+    assert_eq!(100, engine.get_account(1).unwrap().get_available_balance());
+    assert_eq!(1, engine.iter_accounts().count());
+    assert!(engine.get_transaction(2).unwrap().is_none());
+
+    engine.withdrawal(1, 2, 99).unwrap();
+    assert_eq!(0, engine.get_account(1).unwrap().get_available_balance());
+    assert_eq!(
+        Err(TransactionIdAlreadyProcessed),
+        engine.dry_run(withdrawal)
+    );
+}
+
+#[test]
+fn apply() {
+    let currency = Currency::default();
+    let mut engine = TransactionEngine::init();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    engine.subscribe(move |event| sender.send(*event).unwrap());
+
+    // Every outcome is returned as the same event the observers are notified of.
+    let operations = [
+        Operation::Deposit {
+            client: 1,
+            tx: 1,
+            amount: 100,
+            currency,
+        },
+        Operation::Transfer {
+            client: 1,
+            tx: 2,
+            to: 2,
+            amount: 150,
+            currency,
+        },
+        Operation::Dispute {
+            client: 1,
+            tx: 1,
+            amount: Some(40),
+        },
+    ];
+    let events = operations.map(|operation| engine.apply(operation));
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), events);
+
+    match events[0] {
+        Event::DepositAccepted(receipt) => {
+            assert_eq!((1, 1, 100), (receipt.client, receipt.tx, receipt.total))
+        }
+        event => panic!("unexpected event: {:?}", event),
+    }
+    match events[1] {
+        Event::Rejected(rejection) => {
+            assert_eq!(OperationKind::Transfer, rejection.kind);
+            assert_eq!((Some(2), Some(150)), (rejection.tx, rejection.amount));
+        }
+        event => panic!("unexpected event: {:?}", event),
+    }
+    match events[2] {
+        Event::FundsHeld(receipt) => assert_eq!((60, 40), (receipt.available, receipt.held)),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    let errors = events.map(|event| event.get_error());
+    assert_eq!([None, Some(InsufficientAvailableFunds), None], errors);
+    assert_eq!([true, false, true], events.map(|event| event.is_accepted()));
+    assert!(engine.get_account(2).is_none());
+}
+
+#[test]
+fn rejected_cross_client_disputes() {
+    let mut engine = TransactionEngine::init();
+    engine.set_cross_client_dispute_policy(CrossClientDisputePolicy::ChargeDisputingClient);
+    engine.set_withdrawal_dispute_policy(WithdrawalDisputePolicy::Reject);
+    engine.deposit(1, 1, 1000).unwrap();
+    engine.withdrawal(1, 2, 100).unwrap();
+
+    // A rejected dispute doesn't leave an empty account behind for the disputing client.
+    assert_eq!(Err(WithdrawalNotDisputable), engine.dispute(9, 2));
+    assert!(engine.get_account(9).is_none());
+    assert_eq!(
+        Err(InvalidDisputeAmount),
+        engine.dispute_partial(8, 1, 2000)
+    );
+    assert!(engine.get_account(8).is_none());
+    assert_eq!(1, engine.iter_accounts().count());
+
+    // An accepted dispute creates it.
+    engine.dispute_partial(7, 1, 300).unwrap();
+    assert_eq!(-300, engine.get_account(7).unwrap().get_available_balance());
+}
+
+#[test]
+fn transfer_disputed_by_recipient() {
+    let mut engine = TransactionEngine::init();
+    engine.set_cross_client_dispute_policy(CrossClientDisputePolicy::ChargeDisputingClient);
+    engine.deposit(1, 1, 100).unwrap();
+    engine.transfer(1, 2, 2, 40).unwrap();
+
+    // Both sides of the transfer are applied to the recipient's account, so no money vanishes.
+    engine.dispute(2, 2).unwrap();
+    let account = engine.get_account(2).unwrap();
+    assert_eq!(
+        (0, 40),
+        (account.get_available_balance(), account.get_total_balance())
+    );
+    engine.chargeback(2, 2).unwrap();
+    let account = engine.get_account(2).unwrap();
+    assert_eq!(40, account.get_total_balance());
+    assert_eq!(AccountStatus::Locked, account.get_status());
+    assert_eq!(60, engine.get_account(1).unwrap().get_total_balance());
+    let total = engine.iter_accounts().map(|(_, a)| a.get_total_balance());
+    assert_eq!(100, total.sum::<i64>());
+}
+
+#[test]
+fn withdrawal_dispute_policies() {
+    let balances = |engine: &TransactionEngine| {
+        let account = engine.get_account(1).unwrap();
+        (
+            account.get_available_balance(),
+            account.get_held_balance(),
+            account.get_total_balance(),
+        )
+    };
+
+    // By default, no funds are held and a chargeback credits the withdrawal back.
+    let mut engine = TransactionEngine::init();
+    assert_eq!(
+        WithdrawalDisputePolicy::NoHold,
+        engine.get_withdrawal_dispute_policy()
+    );
+    engine.deposit(1, 1, 100).unwrap();
+    engine.withdrawal(1, 2, 40).unwrap();
+    engine.dispute(1, 2).unwrap();
+    assert_eq!((60, 0, 60), balances(&engine));
+    engine.chargeback(1, 2).unwrap();
+    assert_eq!((100, 0, 100), balances(&engine));
+
+    // A withdrawal dispute can be rejected, but a deposit can still be disputed.
+    let mut engine = TransactionEngine::init();
+    engine.set_withdrawal_dispute_policy(WithdrawalDisputePolicy::Reject);
+    engine.deposit(1, 1, 100).unwrap();
+    engine.withdrawal(1, 2, 40).unwrap();
+    assert_eq!(Err(WithdrawalNotDisputable), engine.dispute(1, 2));
+    assert_eq!(
+        TransactionState::Accepted,
+        engine.get_transaction(2).unwrap().unwrap().get_state()
+    );
+    engine.dispute(1, 1).unwrap();
+    assert_eq!((-40, 100, 60), balances(&engine));
+
+    // A withdrawal dispute can hold a pending credit, which is released by a chargeback...
+    let mut engine = TransactionEngine::init();
+    engine.set_withdrawal_dispute_policy(WithdrawalDisputePolicy::HoldCredit);
+    engine.deposit(1, 1, 100).unwrap();
+    engine.withdrawal(1, 2, 40).unwrap();
+    engine.dispute_partial(1, 2, 30).unwrap();
+    assert_eq!((60, 30, 90), balances(&engine));
+    engine.chargeback(1, 2).unwrap();
+    assert_eq!((90, 0, 90), balances(&engine));
+
+    // ...or cancelled by a resolve.
+    engine.unlock(1).unwrap();
+    engine.dispute(1, 2).unwrap();
+    assert_eq!((90, 10, 100), balances(&engine));
+    engine.resolve(1, 2).unwrap();
+    assert_eq!((90, 0, 90), balances(&engine));
+
+    // A withdrawal is resolved or charged back according to the policy it was disputed with,
+    // even if the policy was changed since then.
+    let mut engine = TransactionEngine::init();
+    engine.deposit(1, 1, 100).unwrap();
+    engine.withdrawal(1, 2, 40).unwrap();
+    engine.dispute(1, 2).unwrap();
+    engine.set_withdrawal_dispute_policy(WithdrawalDisputePolicy::HoldCredit);
+    engine.resolve(1, 2).unwrap();
+    assert_eq!((60, 0, 60), balances(&engine));
+    engine.dispute_partial(1, 2, 10).unwrap();
+    engine.set_withdrawal_dispute_policy(WithdrawalDisputePolicy::NoHold);
+    engine.dispute_partial(1, 2, 20).unwrap();
+    assert_eq!((60, 30, 90), balances(&engine));
+    engine.set_withdrawal_dispute_policy(WithdrawalDisputePolicy::Reject);
+    assert_eq!(Err(WithdrawalNotDisputable), engine.dispute(1, 2));
+    engine.chargeback(1, 2).unwrap();
+    assert_eq!((90, 0, 90), balances(&engine));
+}
+
+#[test]
+fn negative_balance_dispute_policies() {
+    let balances = |engine: &TransactionEngine| {
+        let account = engine.get_account(1).unwrap();
+        let held = account.get_held_balance();
+        (
+            account.get_available_balance(),
+            held,
+            account.get_shortfall(),
+        )
+    };
+    let init = |policy| {
+        let mut engine = TransactionEngine::init();
+        engine.set_negative_balance_dispute_policy(policy);
+        engine.deposit(1, 1, 100).unwrap();
+        engine.withdrawal(1, 2, 70).unwrap();
+        engine
+    };
+
+    // By default, the available balance can become negative.
+    let mut engine = init(NegativeBalanceDisputePolicy::Allow);
+    engine.dispute(1, 1).unwrap();
+    assert_eq!((-70, 100, 0), balances(&engine));
+
+    // The dispute can be rejected, unless it fits in the available balance.
+    let mut engine = init(NegativeBalanceDisputePolicy::Reject);
+    assert_eq!(Err(DisputeExceedsAvailableFunds), engine.dispute(1, 1));
+    engine.dispute_partial(1, 1, 30).unwrap();
+    assert_eq!((0, 30, 0), balances(&engine));
+
+    // The hold can be capped at the available balance, and the shortfall is recorded.
+    let mut engine = init(NegativeBalanceDisputePolicy::CapHold);
+    engine.dispute(1, 1).unwrap();
+    assert_eq!((0, 30, 70), balances(&engine));
+    assert_eq!(
+        70,
+        engine.get_transaction(1).unwrap().unwrap().get_shortfall()
+    );
+
+    // A resolve settles the shortfall first, then releases the held funds.
+    engine.resolve_partial(1, 1, 80).unwrap();
+    assert_eq!((10, 20, 0), balances(&engine));
+    engine.resolve(1, 1).unwrap();
+    assert_eq!((30, 0, 0), balances(&engine));
+
+    // A chargeback settles the shortfall first by debiting the available balance.
+    engine.dispute(1, 1).unwrap();
+    engine.chargeback_partial(1, 1, 50).unwrap();
+    assert_eq!((-50, 30, 20), balances(&engine));
+    engine.unlock(1).unwrap();
+    engine.chargeback(1, 1).unwrap();
+    assert_eq!((-70, 0, 0), balances(&engine));
+    assert_eq!(-70, engine.get_account(1).unwrap().get_total_balance());
+
+    // The policy also applies to the recipient's side of a transfer dispute.
+    let recipient = |engine: &TransactionEngine| {
+        let account = engine.get_account(2).unwrap();
+        let held = account.get_held_balance();
+        (
+            account.get_available_balance(),
+            held,
+            account.get_shortfall(),
+        )
+    };
+    let mut engine = init(NegativeBalanceDisputePolicy::Reject);
+    engine.transfer(1, 2, 3, 30).unwrap();
+    engine.withdrawal(2, 4, 20).unwrap();
+    assert_eq!(Err(DisputeExceedsAvailableFunds), engine.dispute(1, 3));
+    let mut engine = init(NegativeBalanceDisputePolicy::CapHold);
+    engine.transfer(1, 2, 3, 30).unwrap();
+    engine.withdrawal(2, 4, 20).unwrap();
+    engine.dispute(1, 3).unwrap();
+    assert_eq!((0, 10, 20), recipient(&engine));
+    engine.chargeback(1, 3).unwrap();
+    assert_eq!((-20, 0, 0), recipient(&engine));
+    assert_eq!(-20, engine.get_account(2).unwrap().get_total_balance());
+}
+
+#[test]
+fn storage_backends() {
+    fn run<S: Storage>(engine: &mut TransactionEngine<S>) {
+        engine.set_dispute_window(Some(DisputeWindow::Transactions(3)));
+        engine.deposit(1, 0, 100).unwrap();
+        engine.deposit(2, 7, 200).unwrap();
+        engine.transfer(2, 3, 8, 50).unwrap();
+        engine.withdrawal(1, 1, 30).unwrap();
+        engine.dispute(2, 7).unwrap();
+        engine.deposit(1, 2, 10).unwrap();
+        engine.deposit(1, 5, 10).unwrap();
+        engine.chargeback(2, 7).unwrap();
+        assert_eq!(Err(DisputeWindowExpired), engine.dispute(1, 0));
+        assert_eq!(Err(TransactionIdAlreadyProcessed), engine.deposit(1, 8, 1));
+    }
+
+    let mut hash_map = TransactionEngine::init();
+    let mut vec = TransactionEngine::with_storage(VecStorage::init()).unwrap();
+    run(&mut hash_map);
+    run(&mut vec);
+
+    let balances = |(id, account): (ClientId, &Client)| {
+        let balances = (account.get_available_balance(), account.get_total_balance());
+        (id, balances, account.get_status())
+    };
+    let mut accounts = hash_map.iter_accounts().map(balances).collect::<Vec<_>>();
+    accounts.sort_by_key(|&(id, _, _)| id);
+    assert_eq!(
+        accounts,
+        vec.iter_accounts().map(balances).collect::<Vec<_>>()
+    );
+    assert!(vec.get_account(2).unwrap().is_locked());
+    assert_eq!(-50, vec.get_account(2).unwrap().get_total_balance());
+    assert!(vec.get_account(4).is_none());
+
+    let mut transactions = hash_map
+        .iter_transactions()
+        .map(|t| t.unwrap().get_id())
+        .collect::<Vec<_>>();
+    transactions.sort_unstable();
+    assert_eq!(vec![1, 2, 5], transactions);
+    let transactions = vec
+        .iter_transactions()
+        .map(|t| t.unwrap().get_id())
+        .collect::<Vec<_>>();
+    assert_eq!(vec![1, 2, 5], transactions);
+}
+
+#[test]
+fn custom_storage() {
+    // A storage backend outside this crate can only persist transactions as bytes.
+    #[derive(Default)]
+    struct BytesStorage {
+        clients: HashMap<ClientId, Client>,
+        transactions: HashMap<u32, [u8; Transaction::ENCODED_LENGTH]>,
+    }
+
+    impl Storage for BytesStorage {
+        fn get_client(&self, id: ClientId) -> Option<&Client> {
+            self.clients.get(&id)
+        }
+
+        fn get_client_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+            self.clients.get_mut(&id)
+        }
+
+        fn insert_client(&mut self, id: ClientId, client: Client) {
+            self.clients.insert(id, client);
+        }
+
+        fn remove_client(&mut self, id: ClientId) {
+            self.clients.remove(&id);
+        }
+
+        fn iter_clients(&self) -> Box<dyn Iterator<Item = (ClientId, &Client)> + '_> {
+            Box::new(self.clients.iter().map(|(&id, client)| (id, client)))
+        }
+
+        fn get_transaction(&self, tx: u32) -> io::Result<Option<Transaction>> {
+            self.transactions.get(&tx).map(decode).transpose()
+        }
+
+        fn insert_transaction(&mut self, tx: u32, transaction: Transaction) -> io::Result<()> {
+            self.transactions.insert(tx, transaction.to_bytes());
+            Ok(())
+        }
+
+        fn remove_transaction(&mut self, tx: u32) -> io::Result<()> {
+            self.transactions.remove(&tx);
+            Ok(())
+        }
+
+        fn iter_transactions(&self) -> TransactionIter<'_> {
+            let transactions = self.transactions.iter();
+            Box::new(transactions.map(|(&tx, bytes)| Ok((tx, decode(bytes)?))))
+        }
+
+        fn clear(&mut self) -> io::Result<()> {
+            self.clients.clear();
+            self.transactions.clear();
+            Ok(())
+        }
+    }
+
+    fn decode(bytes: &[u8; Transaction::ENCODED_LENGTH]) -> io::Result<Transaction> {
+        let error = || io::Error::new(io::ErrorKind::InvalidData, "invalid transaction");
+        Transaction::from_bytes(bytes).ok_or_else(error)
+    }
+
+    let mut engine = TransactionEngine::with_storage(BytesStorage::default()).unwrap();
+    engine.set_withdrawal_dispute_policy(WithdrawalDisputePolicy::HoldCredit);
+    let eur = "EUR".parse::<Currency>().unwrap();
+    engine.deposit_in(1, 1, 100, eur).unwrap();
+    engine.deposit(1, 4, 100).unwrap();
+    engine.transfer(1, 2, 2, 30).unwrap();
+    engine.withdrawal(1, 3, 50).unwrap();
+    engine.dispute_partial(1, 3, 20).unwrap();
+    engine.dispute(1, 2).unwrap();
+
+    let transfer = engine.get_transaction(2).unwrap().unwrap();
+    assert_eq!(Some(2), transfer.get_recipient());
+    assert_eq!(TransactionState::Disputed, transfer.get_state());
+    assert_eq!(30, transfer.get_disputed_amount());
+    assert_eq!(
+        eur,
+        engine.get_transaction(1).unwrap().unwrap().get_currency()
+    );
+
+    // The pending credit of the withdrawal dispute survives the round trip through bytes.
+    engine.set_withdrawal_dispute_policy(WithdrawalDisputePolicy::NoHold);
+    engine.resolve(1, 3).unwrap();
+    assert_eq!(0, engine.get_account(1).unwrap().get_held_balance());
+    let invalid = [u8::MAX; Transaction::ENCODED_LENGTH];
+    assert!(Transaction::from_bytes(&invalid).is_none());
+}
+
+#[test]
+fn storage_failure() {
+    // A storage whose transactions can't be written while `failing` is set.
+    struct FailingStorage {
+        inner: HashMapStorage,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl FailingStorage {
+        fn check(&self) -> io::Result<()> {
+            match self.failing.load(Ordering::SeqCst) {
+                true => Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl Storage for FailingStorage {
+        fn get_client(&self, id: ClientId) -> Option<&Client> {
+            self.inner.get_client(id)
+        }
+
+        fn get_client_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+            self.inner.get_client_mut(id)
+        }
+
+        fn insert_client(&mut self, id: ClientId, client: Client) {
+            self.inner.insert_client(id, client)
+        }
+
+        fn remove_client(&mut self, id: ClientId) {
+            self.inner.remove_client(id)
+        }
+
+        fn iter_clients(&self) -> Box<dyn Iterator<Item = (ClientId, &Client)> + '_> {
+            self.inner.iter_clients()
+        }
+
+        fn get_transaction(&self, tx: u32) -> io::Result<Option<Transaction>> {
+            self.inner.get_transaction(tx)
+        }
+
+        fn insert_transaction(&mut self, tx: u32, transaction: Transaction) -> io::Result<()> {
+            self.check()?;
+            self.inner.insert_transaction(tx, transaction)
+        }
+
+        fn remove_transaction(&mut self, tx: u32) -> io::Result<()> {
+            self.check()?;
+            self.inner.remove_transaction(tx)
+        }
+
+        fn iter_transactions(&self) -> TransactionIter<'_> {
+            self.inner.iter_transactions()
+        }
+
+        fn clear(&mut self) -> io::Result<()> {
+            self.check()?;
+            self.inner.clear()
+        }
+    }
+
+    let failing = Arc::new(AtomicBool::new(false));
+    let storage = FailingStorage {
+        inner: HashMapStorage::init(),
+        failing: failing.clone(),
+    };
+    let mut engine = TransactionEngine::with_storage(storage).unwrap();
+    engine.set_fee_policy(Some(Box::new(|_, _, _| 1)));
+    engine.set_fee_account(Some(0));
+    engine.deposit(1, 1, 100).unwrap();
+    let balances = |engine: &TransactionEngine<FailingStorage>, client| {
+        let account = engine.get_account(client).unwrap();
+        (account.get_available_balance(), account.get_held_balance())
+    };
+    assert_eq!((99, 0), balances(&engine, 1));
+    assert_eq!((1, 0), balances(&engine, 0));
+
+    // An operation whose transaction can't be written is rejected, without modifying
+    // (or creating) any client account.
+    failing.store(true, Ordering::SeqCst);
+    assert_eq!(Err(StorageFailure), engine.deposit(1, 2, 50));
+    assert_eq!(Err(StorageFailure), engine.deposit(2, 3, 50));
+    assert_eq!(Err(StorageFailure), engine.transfer(1, 3, 4, 10));
+    assert_eq!(Err(StorageFailure), engine.dispute(1, 1));
+    assert_eq!((99, 0), balances(&engine, 1));
+    assert_eq!((1, 0), balances(&engine, 0));
+    assert!(engine.get_account(2).is_none());
+    assert!(engine.get_account(3).is_none());
+    let state = engine.get_transaction(1).unwrap().unwrap().get_state();
+    assert_eq!(TransactionState::Accepted, state);
+
+    // A failed batch is rejected because of the storage failure.
+    let currency = Currency::default();
+    let batch = [Operation::Deposit {
+        client: 1,
+        tx: 2,
+        amount: 50,
+        currency,
+    }];
+    assert_eq!(StorageFailure, engine.batch(&batch).unwrap_err().error);
+    assert_eq!((99, 0), balances(&engine, 1));
+
+    // The rejected transaction IDs can be used once the storage works again.
+    failing.store(false, Ordering::SeqCst);
+    engine.deposit(1, 2, 50).unwrap();
+    engine.dispute(1, 1).unwrap();
+    assert_eq!((47, 100), balances(&engine, 1));
+}
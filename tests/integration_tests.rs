@@ -6,15 +6,19 @@ use std::process::Command;
 /// from the `tests/input` folder. It then asserts that the actual output
 /// matches the expected output using the output CSV file with that same name
 /// from the `tests/output` folder. All files must have the `.csv` extension.
+///
+/// Any additional arguments are passed to the binary crate after the input path.
 macro_rules! test_csv {
-    ($test_name:ident) => {
+    ($test_name:ident $(, $arg:expr)*) => {
         #[test]
         fn $test_name() {
             let input_path = concat!("tests/input/", stringify!($test_name), ".csv");
             let output_path = concat!("tests/output/", stringify!($test_name), ".csv");
 
+            let args: &[&str] = &[$($arg),*];
             let output = Command::new("cargo")
                 .args(["run", "--release", "--", input_path])
+                .args(args)
                 .output()
                 .unwrap();
 
@@ -26,12 +30,65 @@ macro_rules! test_csv {
     };
 }
 
+/// This macro creates a test that runs the binary crate using the `tests/input/rejects.csv`
+/// input file (with CRLF line terminators, quoted and multi-line fields, and a malformed
+/// record), with a rejects file in the given format. It then asserts that the actual output
+/// matches `tests/output/rejects.csv`, that the actual rejects file matches the expected one
+/// with the test's name from the `tests/rejects` folder, and that a summary is printed.
+macro_rules! test_rejects {
+    ($test_name:ident, $format:literal, $extension:literal) => {
+        #[test]
+        fn $test_name() {
+            let input_path = "tests/input/rejects.csv";
+            let output_path = "tests/output/rejects.csv";
+            let file_name = concat!(stringify!($test_name), ".", $extension);
+            let rejects_path = std::env::temp_dir().join(file_name);
+            let expected_rejects_path = format!("tests/rejects/{}", file_name);
+
+            let output = Command::new("cargo")
+                .args(["run", "--release", "--", input_path])
+                .arg("--rejects")
+                .arg(&rejects_path)
+                .args(["--rejects-format", $format])
+                .output()
+                .unwrap();
+
+            let actual = String::from_utf8(output.stdout).unwrap();
+            let expected = std::fs::read_to_string(output_path).unwrap();
+            assert_eq!(actual, expected);
+
+            let actual = std::fs::read_to_string(&rejects_path).unwrap();
+            let expected = std::fs::read_to_string(expected_rejects_path).unwrap();
+            assert_eq!(actual, expected);
+
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(stderr.contains("summary: 12 rows\n  accepted: 5\n  unparsable: 3\n"));
+            assert!(stderr.contains("  rejected (InsufficientAvailableFunds): 2\n"));
+        }
+    };
+}
+
+test_csv!(authorization);
 test_csv!(basic);
 test_csv!(chargeback_deposit);
 test_csv!(chargeback_negative);
 test_csv!(chargeback_withdrawal);
+test_csv!(
+    cross_client_disputing,
+    "--cross-client-disputes",
+    "disputing"
+);
+test_csv!(cross_client_original, "--cross-client-disputes", "original");
+test_csv!(cross_client_reject, "--cross-client-disputes", "reject");
 test_csv!(dispute_deposit);
 test_csv!(dispute_negative);
 test_csv!(dispute_withdrawal);
+test_csv!(dry_run, "--dry-run");
+test_csv!(multi_currency);
+test_csv!(precision, "--precision", "JPY=0", "--precision", "BTC=8");
+test_csv!(partial_dispute);
 test_csv!(resolve_deposit);
 test_csv!(resolve_withdrawal);
+test_csv!(transfer);
+test_rejects!(rejects_csv, "csv", "csv");
+test_rejects!(rejects_json, "json", "jsonl");
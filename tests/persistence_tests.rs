@@ -0,0 +1,261 @@
+use std::path::PathBuf;
+use transaction_engine::{Currency, DiskStorage, Operation, TransactionEngine};
+use transaction_engine::{TransactionState, VecStorage};
+
+/// Returns a path in the temporary directory that is unique to this test and process.
+/// Any file left over from a previous run at that path is removed.
+fn temp_path(name: &str) -> PathBuf {
+    let file_name = format!("transaction_engine_{}_{}", name, std::process::id());
+    let path = std::env::temp_dir().join(file_name);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn journal_replay() {
+    let path = temp_path("journal_replay.jsonl");
+
+    let mut engine = TransactionEngine::init();
+    engine.open_journal(&path).unwrap();
+    engine.deposit(1, 1, 100).unwrap();
+    engine.deposit(2, 2, 200).unwrap();
+    engine.withdrawal(1, 3, 50).unwrap();
+    engine.withdrawal(2, 4, 500).unwrap_err();
+    engine.dispute(2, 2).unwrap();
+    drop(engine);
+
+    let mut engine = TransactionEngine::init();
+    engine.open_journal(&path).unwrap();
+
+    let client1 = engine.get_account(1).unwrap();
+    assert_eq!(50, client1.get_available_balance());
+    assert_eq!(50, client1.get_total_balance());
+
+    let client2 = engine.get_account(2).unwrap();
+    assert_eq!(0, client2.get_available_balance());
+    assert_eq!(200, client2.get_held_balance());
+
+    // The replayed transactions are still known to the engine.
+    engine.deposit(1, 1, 100).unwrap_err();
+    engine.chargeback(2, 2).unwrap();
+    assert!(engine.get_account(2).unwrap().is_locked());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn journal_batch() {
+    let path = temp_path("journal_batch.jsonl");
+    let currency = Currency::default();
+
+    let mut engine = TransactionEngine::init();
+    engine.open_journal(&path).unwrap();
+    let accepted = [
+        Operation::Deposit {
+            client: 1,
+            tx: 1,
+            amount: 100,
+            currency,
+        },
+        Operation::Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: 30,
+            currency,
+        },
+    ];
+    let rejected = [
+        Operation::Deposit {
+            client: 2,
+            tx: 3,
+            amount: 100,
+            currency,
+        },
+        Operation::Dispute {
+            client: 2,
+            tx: 1,
+            amount: None,
+        },
+    ];
+    engine.batch(&accepted).unwrap();
+    engine.batch(&rejected).unwrap_err();
+    drop(engine);
+
+    // The accepted batch is recorded as a single line, and the rejected batch isn't recorded.
+    let journal = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(1, journal.lines().count());
+
+    let mut engine = TransactionEngine::init();
+    engine.open_journal(&path).unwrap();
+    assert_eq!(70, engine.get_account(1).unwrap().get_total_balance());
+    assert!(engine.get_account(2).is_none());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn journal_incomplete_last_line() {
+    let path = temp_path("journal_incomplete_last_line.jsonl");
+
+    let mut engine = TransactionEngine::init();
+    engine.open_journal(&path).unwrap();
+    engine.deposit(1, 1, 100).unwrap();
+    drop(engine);
+
+    // Simulate a crash in the middle of an append.
+    let mut contents = std::fs::read_to_string(&path).unwrap();
+    contents.push_str(r#"{"type":"deposit","client":1,"#);
+    std::fs::write(&path, contents).unwrap();
+
+    let mut engine = TransactionEngine::init();
+    engine.open_journal(&path).unwrap();
+    engine.deposit(1, 2, 100).unwrap();
+    drop(engine);
+
+    let mut engine = TransactionEngine::init();
+    engine.open_journal(&path).unwrap();
+    assert_eq!(200, engine.get_account(1).unwrap().get_total_balance());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn snapshot_round_trip() {
+    let journal_path = temp_path("snapshot_round_trip.jsonl");
+    let snapshot_path = temp_path("snapshot_round_trip.snapshot");
+
+    let mut engine = TransactionEngine::init();
+    engine.open_journal(&journal_path).unwrap();
+    engine.deposit(1, 1, 100).unwrap();
+    engine.deposit(1, 2, 200).unwrap();
+    engine.deposit(2, 3, 300).unwrap();
+    engine.deposit(3, 4, 400).unwrap();
+    engine.dispute(1, 2).unwrap();
+    engine.dispute(3, 4).unwrap();
+    engine.chargeback(3, 4).unwrap();
+    engine.save_snapshot(&snapshot_path).unwrap();
+
+    // These transactions are only recorded in the journal's tail.
+    engine.withdrawal(2, 5, 100).unwrap();
+    engine.dispute(2, 3).unwrap();
+
+    let mut restored = TransactionEngine::init();
+    restored.load_snapshot(&snapshot_path).unwrap();
+    restored.open_journal(&journal_path).unwrap();
+
+    // Both engines must have exactly the same client accounts.
+    let mut expected = engine.iter_accounts().collect::<Vec<_>>();
+    let mut actual = restored.iter_accounts().collect::<Vec<_>>();
+    expected.sort_by_key(|(id, _)| *id);
+    actual.sort_by_key(|(id, _)| *id);
+    assert_eq!(expected.len(), actual.len());
+    for ((expected_id, expected), (actual_id, actual)) in expected.iter().zip(&actual) {
+        assert_eq!(expected_id, actual_id);
+        assert_eq!(
+            expected.get_available_balance(),
+            actual.get_available_balance()
+        );
+        assert_eq!(expected.get_held_balance(), actual.get_held_balance());
+        assert_eq!(expected.get_total_balance(), actual.get_total_balance());
+        assert_eq!(expected.is_locked(), actual.is_locked());
+    }
+
+    // Both engines must have exactly the same transactions in the same states.
+    for engine in [&mut engine, &mut restored] {
+        engine.deposit(1, 1, 100).unwrap_err();
+        engine.dispute(1, 1).unwrap();
+        engine.dispute(1, 2).unwrap_err();
+        engine.resolve(1, 2).unwrap();
+        engine.chargeback(2, 3).unwrap();
+        engine.resolve(3, 4).unwrap_err();
+        engine.dispute(2, 6).unwrap_err();
+    }
+
+    std::fs::remove_file(&journal_path).unwrap();
+    std::fs::remove_file(&snapshot_path).unwrap();
+}
+
+#[test]
+fn snapshot_across_storage_backends() {
+    let snapshot_path = temp_path("snapshot_across_storage_backends.snapshot");
+
+    let mut engine = TransactionEngine::init();
+    engine.deposit(1, 1, 100).unwrap();
+    engine.deposit(2, 2, 200).unwrap();
+    engine.dispute(2, 2).unwrap();
+    engine.save_snapshot(&snapshot_path).unwrap();
+
+    // A snapshot saved with one storage backend can be restored with another one.
+    let mut restored = TransactionEngine::with_storage(VecStorage::with_capacity(16)).unwrap();
+    restored.load_snapshot(&snapshot_path).unwrap();
+    assert_eq!(2, restored.iter_accounts().count());
+    assert_eq!(100, restored.get_account(1).unwrap().get_total_balance());
+    assert_eq!(200, restored.get_account(2).unwrap().get_held_balance());
+    restored.deposit(1, 1, 100).unwrap_err();
+    restored.chargeback(2, 2).unwrap();
+    assert!(restored.get_account(2).unwrap().is_locked());
+
+    std::fs::remove_file(&snapshot_path).unwrap();
+}
+
+#[test]
+fn disk_storage() {
+    let path = temp_path("disk_storage.transactions");
+    let snapshot_path = temp_path("disk_storage.snapshot");
+
+    let storage = DiskStorage::open(&path, 2).unwrap();
+    let mut engine = TransactionEngine::with_storage(storage).unwrap();
+    engine.deposit(1, 1, 100).unwrap();
+    engine.transfer(1, 2, 2, 30).unwrap();
+    engine.authorize(1, 3, 20).unwrap();
+    engine.deposit(2, 10, 50).unwrap();
+    engine.deposit(2, 4, 10).unwrap();
+    engine.deposit(3, 5, 70).unwrap();
+    assert_eq!(6, engine.iter_transactions().count());
+    assert!(std::fs::metadata(&path).unwrap().len() > 0);
+
+    // Spilled transactions are still known to the engine, and can be disputed.
+    engine.deposit(1, 1, 100).unwrap_err();
+    engine.dispute(1, 2).unwrap();
+    engine.dispute(1, 1).unwrap();
+    engine.resolve(1, 1).unwrap();
+    engine.capture(1, 3).unwrap();
+    engine.dispute(2, 10).unwrap();
+    engine.chargeback(2, 10).unwrap();
+    engine.chargeback(2, 10).unwrap_err();
+    let state = engine.get_transaction(10).unwrap().unwrap().get_state();
+    assert_eq!(TransactionState::Reversed, state);
+    engine.deposit(3, 6, 10).unwrap();
+    engine.deposit(3, 7, 10).unwrap();
+
+    let client1 = engine.get_account(1).unwrap();
+    assert_eq!(50, client1.get_available_balance());
+    assert_eq!(0, client1.get_held_balance());
+    let client2 = engine.get_account(2).unwrap();
+    assert_eq!(10, client2.get_available_balance());
+    assert_eq!(30, client2.get_held_balance());
+    assert!(client2.is_locked());
+
+    // A snapshot includes the spilled transactions.
+    engine.save_snapshot(&snapshot_path).unwrap();
+    let mut restored = TransactionEngine::init();
+    restored.load_snapshot(&snapshot_path).unwrap();
+    let mut expected = engine
+        .iter_transactions()
+        .map(|t| t.map(|t| (t.get_id(), t.get_state())))
+        .collect::<std::io::Result<Vec<_>>>()
+        .unwrap();
+    let mut actual = restored
+        .iter_transactions()
+        .map(|t| t.map(|t| (t.get_id(), t.get_state())))
+        .collect::<std::io::Result<Vec<_>>>()
+        .unwrap();
+    expected.sort_by_key(|&(tx, _)| tx);
+    actual.sort_by_key(|&(tx, _)| tx);
+    assert_eq!(8, actual.len());
+    assert_eq!(expected, actual);
+    restored.resolve(1, 2).unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&snapshot_path).unwrap();
+}